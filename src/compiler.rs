@@ -0,0 +1,300 @@
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::lexer::{Node, NodeKind, PatternKind};
+use crate::vmstate::SendCache;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Let,
+    Set,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Op {
+    Here,
+    Me,
+    /// Leave the method with the popped answer or the recipient.
+    Return(bool),
+    Repeat,
+    /// Leave the loop (or go to its next iteration) with the popped answer or None.
+    Break(bool),
+    Continue(bool),
+    Int(isize),
+    Float(f64),
+    String(String),
+    /// Get field or call keyword-method of the context.
+    Name(String),
+    /// Pop recipient, call its keyword-method or match the name as a message.
+    Send(String, SendCache),
+    /// Pop message and recipient, match method of the recipient.
+    Dispatch(SendCache),
+    /// Like `Send` and `Dispatch`, but methods are looked up from the parent of the running method's owner.
+    SuperSend(String),
+    SuperDispatch,
+    Copy,
+    /// Create block object with parameter, that runs the code at the current contexts.
+    Block(Option<String>, Rc<Code>),
+    /// Discard the answer of the previous message chain.
+    Pop,
+    /// Pop object and enter into it.
+    EnterAt,
+    /// Enter into a new copy of the current context.
+    EnterSubContext,
+    LeaveContext,
+    /// Check that fields of the current context can be accessed.
+    CheckAccess(Access),
+    Let(String),
+    Set(String),
+    DefineKw(String, Rc<Code>),
+    /// Pop pattern object and define method with alias and guard in the current context.
+    DefinePt(Option<String>, Option<Rc<Code>>, Rc<Code>),
+    DefineEq(Option<String>, Option<Rc<Code>>, Rc<Code>),
+    /// Pop target object and import module into it.
+    Import(String),
+    Error(String),
+}
+
+/// Compiled message chain: operations and their source lines.
+#[derive(Debug, Default)]
+pub struct Code {
+    pub ops: Vec<Op>,
+    pub lines: Vec<usize>,
+}
+
+/// Codes are same, if they have same operations; lines don't matter.
+impl PartialEq for Code {
+    fn eq(&self, other: &Self) -> bool {
+        self.ops == other.ops
+    }
+}
+
+impl Code {
+    fn push(&mut self, op: Op, line: usize) {
+        self.ops.push(op);
+        self.lines.push(line);
+    }
+}
+
+pub fn compile(node: &Node) -> Code {
+    let mut code = Code::default();
+    compile_node(&mut code, node);
+    code
+}
+
+fn compile_node(code: &mut Code, node: &Node) {
+    let line = node.line;
+    match node.data.deref() {
+        NodeKind::Here => code.push(Op::Here, line),
+        NodeKind::Me | NodeKind::Super => code.push(Op::Me, line),
+        NodeKind::Return(value) => {
+            if let Some(value) = value {
+                compile_node(code, value);
+            }
+            code.push(Op::Return(value.is_some()), line);
+        }
+        NodeKind::Repeat => code.push(Op::Repeat, line),
+        NodeKind::Break(value) => {
+            if let Some(value) = value {
+                compile_node(code, value);
+            }
+            code.push(Op::Break(value.is_some()), line);
+        }
+        NodeKind::Continue(value) => {
+            if let Some(value) = value {
+                compile_node(code, value);
+            }
+            code.push(Op::Continue(value.is_some()), line);
+        }
+        NodeKind::Name(name) => code.push(Op::Name(name.clone()), line),
+        NodeKind::Int(value) => code.push(Op::Int(*value), line),
+        NodeKind::Float(value) => code.push(Op::Float(*value), line),
+        NodeKind::String(string) => code.push(Op::String(string.clone()), line),
+        NodeKind::Pattern(..) | NodeKind::As(..) => unreachable!(),
+        NodeKind::Message(rec_node, msg_node)
+            if matches!(rec_node.data.deref(), NodeKind::Super) =>
+        {
+            compile_node(code, rec_node);
+            match msg_node.data.deref() {
+                NodeKind::Name(name) => code.push(Op::SuperSend(name.clone()), line),
+                _ => {
+                    compile_node(code, msg_node);
+                    code.push(Op::SuperDispatch, line);
+                }
+            }
+        }
+        NodeKind::Message(rec_node, msg_node) => {
+            compile_node(code, rec_node);
+            match msg_node.data.deref() {
+                NodeKind::Name(name) => {
+                    code.push(Op::Send(name.clone(), SendCache::default()), line)
+                }
+                _ => {
+                    compile_node(code, msg_node);
+                    code.push(Op::Dispatch(SendCache::default()), line);
+                }
+            }
+        }
+        NodeKind::Queue(queue) => compile_queue(code, queue, line),
+        NodeKind::QuickContext(queue) => {
+            code.push(Op::EnterSubContext, line);
+            compile_queue(code, queue, line);
+            code.push(Op::LeaveContext, line);
+        }
+        NodeKind::Copy(node) => {
+            compile_node(code, node);
+            code.push(Op::Copy, node.line);
+        }
+        NodeKind::Block(parameter, body) => {
+            code.push(Op::Block(parameter.clone(), Rc::new(compile(body))), line)
+        }
+        NodeKind::At(context_node, body_node) => {
+            compile_node(code, context_node);
+            code.push(Op::EnterAt, line);
+            compile_node(code, body_node);
+            code.push(Op::LeaveContext, line);
+        }
+        NodeKind::Let(name, value_node) => {
+            code.push(Op::CheckAccess(Access::Let), line);
+            compile_node(code, value_node);
+            code.push(Op::Let(name.clone()), line);
+        }
+        NodeKind::Set(name, value_node) => {
+            code.push(Op::CheckAccess(Access::Set), line);
+            compile_node(code, value_node);
+            code.push(Op::Set(name.clone()), line);
+        }
+        NodeKind::OnDo(patterns, body) => {
+            if patterns.len() != 1 {
+                unreachable!()
+            }
+            let body = Rc::new(compile(body));
+            let (pattern, guard) = &patterns[0];
+            let guard = guard.as_ref().map(|guard| Rc::new(compile(guard)));
+            let (pattern, alias) = match pattern.data.deref() {
+                NodeKind::As(pattern_node, alias) => (pattern_node, Some(alias.clone())),
+                _ => (pattern, None),
+            };
+            match pattern.data.deref() {
+                NodeKind::Pattern(PatternKind::Keyword, name_node) => {
+                    let name = match name_node.data.deref() {
+                        NodeKind::Name(name) => name,
+                        _ => unreachable!(),
+                    };
+                    code.push(Op::DefineKw(name.clone(), body), line)
+                }
+                NodeKind::Pattern(PatternKind::Prototype, node) => {
+                    compile_node(code, node);
+                    code.push(Op::DefinePt(alias, guard, body), line);
+                }
+                NodeKind::Pattern(PatternKind::Equalness, node) => {
+                    compile_node(code, node);
+                    code.push(Op::DefineEq(alias, guard, body), line);
+                }
+                _ => unreachable!(),
+            }
+        }
+        NodeKind::Import(name, node) => {
+            compile_node(code, node);
+            code.push(Op::Import(name.clone()), line);
+        }
+    }
+}
+
+fn compile_queue(code: &mut Code, queue: &[Node], line: usize) {
+    if queue.is_empty() {
+        code.push(Op::Error("Empty block of code".into()), line);
+        return;
+    }
+    for (i, node) in queue.iter().enumerate() {
+        if i > 0 {
+            code.push(Op::Pop, node.line);
+        }
+        compile_node(code, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::lex, parser::parse_str};
+
+    fn compile_str(source: &str) -> Code {
+        compile(&lex(parse_str(source), "<test>"))
+    }
+
+    fn send(name: &str) -> Op {
+        Op::Send(name.into(), SendCache::default())
+    }
+
+    #[test]
+    fn message_chains_are_compiled_to_postfix_ops() {
+        let code = compile_str("let x (1 ++);\nx println;");
+        assert_eq!(
+            code.ops,
+            [
+                Op::CheckAccess(Access::Let),
+                Op::Int(1),
+                send("++"),
+                Op::Let("x".into()),
+                Op::Pop,
+                Op::Name("x".into()),
+                send("println"),
+            ]
+        );
+        assert_eq!(code.lines, [0, 0, 0, 0, 1, 1, 1]);
+
+        let code = compile_str("super foo; super (1);");
+        assert_eq!(
+            code.ops,
+            [
+                Op::Me,
+                Op::SuperSend("foo".into()),
+                Op::Pop,
+                Op::Me,
+                Op::Int(1),
+                Op::SuperDispatch,
+            ]
+        );
+    }
+
+    #[test]
+    fn contexts_are_entered_and_left() {
+        let code = compile_str("at x (let y 2); (1; 2);");
+        assert_eq!(
+            code.ops,
+            [
+                Op::Name("x".into()),
+                Op::EnterAt,
+                Op::CheckAccess(Access::Let),
+                Op::Int(2),
+                Op::Let("y".into()),
+                Op::LeaveContext,
+                Op::Pop,
+                Op::Int(1),
+                Op::Pop,
+                Op::Int(2),
+            ]
+        );
+        assert_eq!(
+            compile_str("()").ops,
+            [Op::Error("Empty block of code".into())]
+        );
+    }
+
+    #[test]
+    fn bodies_and_guards_are_compiled_separately() {
+        let body = || Rc::new(compile_str("n"));
+        assert_eq!(
+            compile_str("on Int as n if (n) do n;").ops,
+            [
+                Op::Name("Int".into()),
+                Op::DefinePt(Some("n".into()), Some(body()), body()),
+            ]
+        );
+        assert_eq!(
+            compile_str("block as n (n);").ops,
+            [Op::Block(Some("n".into()), body())]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+// Readable and JSON dumps of tokens and syntax trees.
+use std::ops::Deref;
+
+use crate::lexer::{Node, NodeKind};
+use crate::parser::{Token, TokenKind};
+
+/// Scalar value of a token or a node.
+enum Scalar {
+    Text(String),
+    String(String),
+    Int(isize),
+    Float(f64),
+}
+
+impl Scalar {
+    fn to_text(&self) -> String {
+        match self {
+            Scalar::Text(text) if is_plain(text) => text.clone(),
+            Scalar::Text(text) | Scalar::String(text) => format!("{text:?}"),
+            Scalar::Int(value) => value.to_string(),
+            Scalar::Float(value) => value.to_string(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Scalar::Text(text) | Scalar::String(text) => json_string(text),
+            Scalar::Int(value) => value.to_string(),
+            Scalar::Float(value) if value.is_finite() => format!("{value:?}"),
+            // JSON has no infinities and NaN.
+            Scalar::Float(value) => json_string(&value.to_string()),
+        }
+    }
+}
+
+/// Entry of a dump: a node of the syntax tree or a labeled group of nodes.
+struct Entry {
+    kind: &'static str,
+    value: Option<Scalar>,
+    line: Option<usize>,
+    children: Vec<Entry>,
+}
+
+impl Entry {
+    fn group(kind: &'static str, children: Vec<Entry>) -> Self {
+        Self {
+            kind,
+            value: None,
+            line: None,
+            children,
+        }
+    }
+}
+
+pub fn dump_tokens(tokens: &[Token], json: bool) -> String {
+    let entries = tokens.iter().map(token_entry);
+    if json {
+        let items = entries.map(|entry| entry_json(&entry)).collect::<Vec<_>>();
+        return format!("[{}]", items.join(","));
+    }
+    let mut out = String::new();
+    for entry in entries {
+        entry_text(&entry, 0, &mut out);
+    }
+    out
+}
+
+pub fn dump_ast(node: &Node, json: bool) -> String {
+    let entry = node_entry(node);
+    if json {
+        return entry_json(&entry);
+    }
+    let mut out = String::new();
+    entry_text(&entry, 0, &mut out);
+    out
+}
+
+fn token_entry(token: &Token) -> Entry {
+    let (kind, value) = match &token.data {
+        TokenKind::Name(name) => ("Name", Some(Scalar::Text(name.clone()))),
+        TokenKind::Int(value) => ("Int", Some(Scalar::Int(*value))),
+        TokenKind::Float(value) => ("Float", Some(Scalar::Float(*value))),
+        TokenKind::String(string) => ("String", Some(Scalar::String(string.clone()))),
+        TokenKind::OpenParen => ("OpenParen", None),
+        TokenKind::CloseParen => ("CloseParen", None),
+        TokenKind::OpenContext => ("OpenContext", None),
+        TokenKind::CloseContext => ("CloseContext", None),
+        TokenKind::EOQ => ("EOQ", None),
+        TokenKind::Here => ("Here", None),
+        TokenKind::Me => ("Me", None),
+        TokenKind::Super => ("Super", None),
+        TokenKind::Copy => ("Copy", None),
+        TokenKind::At => ("At", None),
+        TokenKind::Let => ("Let", None),
+        TokenKind::Set => ("Set", None),
+        TokenKind::On => ("On", None),
+        TokenKind::Do => ("Do", None),
+        TokenKind::As => ("As", None),
+        TokenKind::If => ("If", None),
+        TokenKind::Return => ("Return", None),
+        TokenKind::Repeat => ("Repeat", None),
+        TokenKind::Break => ("Break", None),
+        TokenKind::Continue => ("Continue", None),
+        TokenKind::Import => ("Import", None),
+        TokenKind::Block => ("Block", None),
+    };
+    Entry {
+        kind,
+        value,
+        line: Some(token.line),
+        children: Vec::new(),
+    }
+}
+
+fn node_entry(node: &Node) -> Entry {
+    let text = |text: &String| Some(Scalar::Text(text.clone()));
+    let (kind, value, children) = match node.data.deref() {
+        NodeKind::Here => ("Here", None, vec![]),
+        NodeKind::Me => ("Me", None, vec![]),
+        NodeKind::Super => ("Super", None, vec![]),
+        NodeKind::Return(answer) => ("Return", None, answer.iter().map(node_entry).collect()),
+        NodeKind::Repeat => ("Repeat", None, vec![]),
+        NodeKind::Break(answer) => ("Break", None, answer.iter().map(node_entry).collect()),
+        NodeKind::Continue(answer) => ("Continue", None, answer.iter().map(node_entry).collect()),
+        NodeKind::Name(name) => ("Name", text(name), vec![]),
+        NodeKind::Int(value) => ("Int", Some(Scalar::Int(*value)), vec![]),
+        NodeKind::Float(value) => ("Float", Some(Scalar::Float(*value)), vec![]),
+        NodeKind::String(string) => ("String", Some(Scalar::String(string.clone())), vec![]),
+        NodeKind::Pattern(kind, node) => (
+            "Pattern",
+            Some(Scalar::Text(format!("{kind:?}"))),
+            vec![node_entry(node)],
+        ),
+        NodeKind::As(node, name) => ("As", text(name), vec![node_entry(node)]),
+        NodeKind::Queue(nodes) => ("Queue", None, nodes.iter().map(node_entry).collect()),
+        NodeKind::QuickContext(nodes) => {
+            ("QuickContext", None, nodes.iter().map(node_entry).collect())
+        }
+        NodeKind::Message(recipient, message) => (
+            "Message",
+            None,
+            vec![node_entry(recipient), node_entry(message)],
+        ),
+        NodeKind::Copy(node) => ("Copy", None, vec![node_entry(node)]),
+        NodeKind::Block(parameter, body) => (
+            "Block",
+            parameter.as_ref().and_then(text),
+            vec![node_entry(body)],
+        ),
+        NodeKind::Import(name, node) => ("Import", text(name), vec![node_entry(node)]),
+        NodeKind::At(context, body) => ("At", None, vec![node_entry(context), node_entry(body)]),
+        NodeKind::Let(name, node) => ("Let", text(name), vec![node_entry(node)]),
+        NodeKind::Set(name, node) => ("Set", text(name), vec![node_entry(node)]),
+        NodeKind::OnDo(patterns, body) => {
+            let mut children = Vec::new();
+            for (pattern, guard) in patterns {
+                children.push(node_entry(pattern));
+                if let Some(guard) = guard {
+                    children.push(Entry::group("Guard", vec![node_entry(guard)]));
+                }
+            }
+            children.push(Entry::group("Body", vec![node_entry(body)]));
+            ("OnDo", None, children)
+        }
+    };
+    Entry {
+        kind,
+        value,
+        line: Some(node.line),
+        children,
+    }
+}
+
+/// One entry per line, children are indented under their parent.
+fn entry_text(entry: &Entry, depth: usize, out: &mut String) {
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(entry.kind);
+    if let Some(value) = &entry.value {
+        out.push(' ');
+        out.push_str(&value.to_text());
+    }
+    if let Some(line) = entry.line {
+        out.push_str(&format!("  [line {}]", line + 1));
+    }
+    out.push('\n');
+    for child in &entry.children {
+        entry_text(child, depth + 1, out);
+    }
+}
+
+fn entry_json(entry: &Entry) -> String {
+    let mut fields = vec![format!("\"kind\":{}", json_string(entry.kind))];
+    if let Some(value) = &entry.value {
+        fields.push(format!("\"value\":{}", value.to_json()));
+    }
+    if let Some(line) = entry.line {
+        fields.push(format!("\"line\":{}", line + 1));
+    }
+    if !entry.children.is_empty() {
+        let children = entry.children.iter().map(entry_json).collect::<Vec<_>>();
+        fields.push(format!("\"children\":[{}]", children.join(",")));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_string(string: &str) -> String {
+    let mut out = String::from('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Names are shown without quotes, unless they would be confused with the dump syntax.
+fn is_plain(text: &str) -> bool {
+    !text.is_empty() && !text.contains(|c: char| c.is_whitespace() || c == '"' || c == '[')
+}
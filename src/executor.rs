@@ -1,376 +1,1064 @@
-use crate::lexer::{Node, NodeKind, PatternKind};
-use crate::vmstate::{Body, Pattern, State, Value};
-use std::ops::Deref;
+use crate::compiler::{compile, Access, Code, Op};
+use crate::lexer::Node;
+use crate::vmstate::{Body, Method, Pattern, SendCache, State, Value};
 use std::path::PathBuf;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum Interrupt {
     Exit(usize),
-    Return(usize),
+    /// Answer and the context of the method to leave.
+    Return(usize, usize),
     Repeat,
+    /// Call of the method, that replaces the running method: (recipient, method, arg).
+    TailCall(usize, Box<Method>, (String, usize)),
+    /// Answer and the scope of the loops to leave, see `loop_scope'.
+    Break(usize, usize),
+    Continue(usize, usize),
     Err(String),
     Error(String, usize, String),
 }
 
+#[cfg(not(test))]
 pub const LIB_DIR: &str = "/home/mazza/dev/proba-lang/lib"; // CHAGE THIS CONSTANT TO WHERE YOU WANT TO STORE LIBS
-
-pub static mut CURRENT_FILE_PATH: String = String::new();
+/// Unit tests load the library of the crate.
+#[cfg(test)]
+pub const LIB_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/lib");
 
 pub fn execute(state: &mut State, node: Node) -> Result<usize, Interrupt> {
-    match node.data.deref() {
-        NodeKind::Here => Ok(state.here().unwrap()),
-        NodeKind::Me => match state.recipient() {
-            Some(ptr) => Ok(ptr),
-            None => Ok(state.contexts.first().unwrap().0),
-        },
-        NodeKind::Return => Err(Interrupt::Return(state.recipient().unwrap())),
-        NodeKind::Repeat => Err(Interrupt::Repeat),
-        NodeKind::Message(rec_node, msg_node) => {
-            // Execute recipient
-            let recipient = execute(state, rec_node.clone())?;
-            let message = match msg_node.data.deref() {
-                NodeKind::Name(ref name) => {
-                    let some_method = state.get_method(recipient, name.clone());
-                    if let Some((_, _, body, fp)) = some_method {
-                        // Try call method of the recipient-object
-                        return execute_method(
-                            state,
-                            recipient,
-                            body.clone(),
-                            (format!("[:{name}]").into(), recipient),
-                            fp.into(),
-                        );
-                    }
-                    execute(state, msg_node.clone())?
-                }
-                _ => execute(state, msg_node.clone())?,
-            };
-            let method = match match_method(state, recipient, message) {
-                Some(method) => method,
-                None => Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    format!(
-                        "Failed to match method for recipient {recipient} and message {message}"
-                    ),
-                ))?,
-            };
-            let name = match method.1 {
-                Pattern::Kw(_) => unreachable!(),
-                Pattern::Eq(_) | Pattern::Pt(_) => format!("[[no as]]").into(),
-                Pattern::EqA(_, name) | Pattern::PtA(_, name) => name.clone(),
-            };
-            execute_method(state, recipient, method.2, (name, message), method.3)
+    run(state, &compile(&node))
+}
+
+/// Run compiled code; on interrupt, leave contexts entered by the code.
+pub fn run(state: &mut State, code: &Code) -> Result<usize, Interrupt> {
+    run_code(state, code, false)
+}
+
+/// Sends in the tail of a method body are answered with `Interrupt::TailCall`.
+fn run_code(state: &mut State, code: &Code, is_method_body: bool) -> Result<usize, Interrupt> {
+    let contexts_len = state.contexts.len();
+    let mut line = 0;
+    let result = run_ops(state, code, is_method_body, &mut line)
+        .map_err(|int| locate(&state.file_path, int, line));
+    if result.is_err() {
+        state.contexts.truncate(contexts_len);
+    }
+    result
+}
+
+/// Give the file and the line of the running code to the error of a native method.
+/// Line 0 marks errors, which are raised where the line is unknown.
+fn locate(file_path: &str, int: Interrupt, line: usize) -> Interrupt {
+    match int {
+        Interrupt::Err(message) | Interrupt::Error(_, 0, message) => {
+            Interrupt::Error(file_path.into(), line, message)
         }
-        NodeKind::Name(name) => {
-            let some_method = state.get_method_ctx(name.clone());
-            let context = match state.contexts.last() {
-                Some(c) => c.0,
+        int => int,
+    }
+}
+
+/// The line of the running op is kept in `current_line', so failures can be located.
+fn run_ops(
+    state: &mut State,
+    code: &Code,
+    is_method_body: bool,
+    current_line: &mut usize,
+) -> Result<usize, Interrupt> {
+    let mut stack: Vec<usize> = Vec::new();
+    for (i, (op, &line)) in code.ops.iter().zip(code.lines.iter()).enumerate() {
+        *current_line = line;
+        let is_tail = is_method_body && i + 1 == code.ops.len();
+        let answer = match op {
+            Op::Here => here(state)?,
+            Op::Me => match state.recipient() {
+                Some(ptr) => ptr,
+                None => global_context(state)?,
+            },
+            Op::Return(has_answer) => match state.method_context() {
+                Some(context) => match has_answer {
+                    true => Err(Interrupt::Return(pop(&mut stack)?, context))?,
+                    false => {
+                        let recipient = state.recipient().ok_or_else(no_context)?;
+                        Err(Interrupt::Return(recipient, context))?
+                    }
+                },
                 None => Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    format!("There is no field or key-method named `{name}'"),
+                    state.file_path.clone(),
+                    line,
+                    "`return' is used outside of a method".into(),
                 ))?,
-            };
-            if let Some((_, pattern, body, fp)) = some_method {
-                // Try call method of the context-object
-                let name = match pattern {
-                    Pattern::Kw(_) | Pattern::Eq(_) | Pattern::Pt(_) => format!("[[no as]]").into(),
-                    Pattern::EqA(_, name) | Pattern::PtA(_, name) => name.clone(),
-                };
-                execute_method(state, context, body.clone(), (name, context), fp.clone())
-            } else if let Some(value) = state.get_field_value_ctx(name.into()) {
-                // Try get field of a context-object
-                match value {
-                    Value::Pointer(ptr) => Ok(ptr),
-                    Value::Int(_) | Value::Float(_) => todo!("Do something with system values"),
+            },
+            Op::Repeat => Err(Interrupt::Repeat)?,
+            Op::Break(has_answer) => Err(Interrupt::Break(
+                loop_answer(state, &mut stack, *has_answer)?,
+                loop_scope(&state.contexts).ok_or_else(no_context)?,
+            ))?,
+            Op::Continue(has_answer) => Err(Interrupt::Continue(
+                loop_answer(state, &mut stack, *has_answer)?,
+                loop_scope(&state.contexts).ok_or_else(no_context)?,
+            ))?,
+            Op::Int(value) => new_int(state, *value)?,
+            Op::Float(_) => Err(Interrupt::Err("Float objects are not supported yet".into()))?,
+            Op::String(text) => new_string(state, text)?,
+            Op::Name(name) => load_name(state, name, line)?,
+            Op::Send(name, cache) => {
+                let recipient = pop(&mut stack)?;
+                if let Some(method) = state.get_method(recipient, name.clone()) {
+                    // Try call method of the recipient-object
+                    let method = method.clone();
+                    let arg = (format!("[:{name}]"), recipient);
+                    call_method(state, recipient, method, arg, is_tail)?
+                } else if let Some(answer) = keyword_not_understood(state, recipient, name, line)? {
+                    answer
+                } else {
+                    let message = load_name(state, name, line)?;
+                    send(state, cache, recipient, message, line, is_tail)?
                 }
-            } else {
-                Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    format!("Undefined keyword-method or field name: {}", name),
-                ))?
             }
-        }
-        NodeKind::Int(value) => {
-            let ptr = state
-                .copy(state.get_field_value(1, "Int".into()).unwrap().unwrap_ptr())
-                .unwrap();
-            state.let_field(ptr, "value".into(), Value::Int(*value));
-            Ok(ptr)
-        }
-        NodeKind::Float(_) => todo!("Create float object"),
-        NodeKind::String(_) => todo!("Create string object"),
-        NodeKind::Pattern(..) => unreachable!(),
-        NodeKind::As(..) => unreachable!(),
-        NodeKind::Queue(queue) => execute_queue(state, &queue, node.line),
-        NodeKind::QuickContext(queue) => {
-            let context = state.contexts.last().unwrap().0;
-            let sub_context = state.copy(context).unwrap();
-            state.contexts.push((sub_context, false));
-            let result = execute_queue(state, &queue, node.line);
-            state.contexts.pop().unwrap();
-            state.clear_garbage(if let Ok(p) = result { vec![p] } else { vec![] });
-            result
-        }
-        NodeKind::Copy(node) => {
-            let ptr = execute(state, node.clone())?;
-            match state.copy(ptr) {
-                Some(p) => Ok(p),
-                None => Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    "Fatal system error: Failed to copy object, because it does not exists".into(),
-                )),
+            Op::Dispatch(cache) => {
+                let message = pop(&mut stack)?;
+                let recipient = pop(&mut stack)?;
+                send(state, cache, recipient, message, line, is_tail)?
             }
-        }
-        NodeKind::At(context_node, body_node) => {
-            let context_ptr = execute(state, context_node.clone())?;
-            state.contexts.push((context_ptr, false));
-            let result = execute(state, body_node.clone());
-            state.contexts.pop().unwrap();
-            state.clear_garbage(if let Ok(p) = result { vec![p] } else { vec![] });
-            result
-        }
-        NodeKind::Let(name, value_node) => {
-            // You can let new field or re-let existing one in a context-object,
-            // only if you entered into it from another context,
-            // that is a copy of the current context-object's creation context.
-            // Exception: the global context.
-            let here = state.here().unwrap();
-            let heres_context = state.objects.iter().find(|obj| obj.0 == here).unwrap().2;
-            if state.here().unwrap() != 1
-                && !state
-                    .relation(state.contexts[state.contexts.len() - 2].0, heres_context)
-                    .is_some()
-            {
-                Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    "Unable to access fileds of the context object here.".into(),
-                ))?
+            Op::SuperSend(name) => {
+                let recipient = pop(&mut stack)?;
+                let super_ptr = super_ptr(state, line)?;
+                if let Some(method) = state.get_method(super_ptr, name.clone()) {
+                    let method = method.clone();
+                    let arg = (format!("[:{name}]"), recipient);
+                    call_method(state, recipient, method, arg, is_tail)?
+                } else {
+                    let message = load_name(state, name, line)?;
+                    super_send(state, super_ptr, recipient, message, line, is_tail)?
+                }
             }
-
-            let value = execute(state, value_node.clone())?;
-            let success = state.let_field(
-                state.contexts.last().unwrap().0,
-                name.clone(),
-                Value::Pointer(value),
-            );
-            match success {
-                Some(_) => Ok(value),
-                None => Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    "Unexpected error".into(),
-                )),
+            Op::SuperDispatch => {
+                let message = pop(&mut stack)?;
+                let recipient = pop(&mut stack)?;
+                let super_ptr = super_ptr(state, line)?;
+                super_send(state, super_ptr, recipient, message, line, is_tail)?
             }
-        }
-        NodeKind::Set(name, value_node) => {
-            // You can set existing field in a context-object,
-            // only if you entered into it from another context,
-            // that is a copy of the current context-object's creation context.
-            // Exception: the global context.
-            let super_context = if state.contexts.len() >= 2 {
-                Some(state.contexts.get(state.contexts.len() - 2).unwrap().0)
-            } else {
-                None
-            };
-            let here = state.here().unwrap();
-            let heres_context = state.objects.iter().find(|obj| obj.0 == here).unwrap().2;
-            if state.here().unwrap() != 1
-                && super_context.is_some()
-                && !state
-                    .relation(super_context.unwrap(), heres_context)
-                    .is_some()
-            {
-                Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    "Unable to access fileds of the context object here.".into(),
-                ))?
+            Op::Copy => {
+                let ptr = pop(&mut stack)?;
+                match state.copy(ptr) {
+                    Some(p) => p,
+                    None => Err(Interrupt::Error(
+                        state.file_path.clone(),
+                        line,
+                        "Fatal system error: Failed to copy object, because it does not exists"
+                            .into(),
+                    ))?,
+                }
             }
-
-            let value = execute(state, value_node.clone())?;
-            let success = state.set_field(
-                state.contexts.last().unwrap().0,
-                name.clone(),
-                Value::Pointer(value),
-            );
-            match success {
-                Some(_) => Ok(value),
-                None => Err(Interrupt::Error(
-                    unsafe { CURRENT_FILE_PATH.clone() },
-                    node.line,
-                    format!("There is no field with name {name}"),
-                )),
+            Op::Block(parameter, body) => {
+                let ptr = copy_global(state, "Block")?;
+                let contexts = captured_contexts(&state.contexts);
+                let file_path = state.file_path.clone();
+                state
+                    .blocks
+                    .push((ptr, parameter.clone(), body.clone(), contexts, file_path));
+                ptr
             }
-        }
-        NodeKind::OnDo(patterns, body) => {
-            if patterns.len() != 1 {
-                unreachable!()
+            Op::Pop => {
+                pop(&mut stack)?;
+                continue;
             }
-            let pattern = match patterns[0].data.deref() {
-                NodeKind::Pattern(PatternKind::Keyword, name_node) => {
-                    let name = match name_node.data.deref() {
-                        NodeKind::Name(name) => name,
-                        _ => unreachable!(),
-                    };
-                    Pattern::Kw(name.clone())
+            Op::EnterAt => {
+                let context_ptr = pop(&mut stack)?;
+                state.contexts.push((context_ptr, false));
+                continue;
+            }
+            Op::EnterSubContext => {
+                let context = here(state)?;
+                let sub_context = state.copy(context).ok_or_else(no_context)?;
+                state.contexts.push((sub_context, false));
+                continue;
+            }
+            Op::LeaveContext => {
+                let ptr = pop(&mut stack)?;
+                state.contexts.pop().ok_or_else(no_context)?;
+                ptr
+            }
+            Op::CheckAccess(access) => {
+                // You can let new field, re-let or set existing one in a context-object,
+                // only if you entered into it from another context,
+                // that is a copy of the current context-object's creation context.
+                // Exception: the global context.
+                let super_context = state.prev_context();
+                let here = here(state)?;
+                let heres_context = state.context_of(here).ok_or_else(no_context)?;
+                let permitted = match (access, super_context) {
+                    (_, _) if here == 1 => true,
+                    (Access::Set, None) => true,
+                    (_, Some(super_context)) => {
+                        state.relation(super_context, heres_context).is_some()
+                    }
+                    (Access::Let, None) => false,
+                };
+                if !permitted {
+                    Err(Interrupt::Error(
+                        state.file_path.clone(),
+                        line,
+                        "Unable to access fileds of the context object here.".into(),
+                    ))?
                 }
-                NodeKind::Pattern(PatternKind::Prototype, node) => {
-                    Pattern::Pt(execute(state, node.clone())?)
+                continue;
+            }
+            Op::Let(name) => {
+                let value = pop(&mut stack)?;
+                let success = state.let_field(here(state)?, name.clone(), Value::Pointer(value));
+                match success {
+                    Some(_) => value,
+                    None => Err(Interrupt::Error(
+                        state.file_path.clone(),
+                        line,
+                        "Unexpected error".into(),
+                    ))?,
                 }
-                NodeKind::Pattern(PatternKind::Equalness, node) => {
-                    Pattern::Eq(execute(state, node.clone())?)
+            }
+            Op::Set(name) => {
+                let value = pop(&mut stack)?;
+                let success = state.set_field(here(state)?, name.clone(), Value::Pointer(value));
+                match success {
+                    Some(_) => value,
+                    None => Err(Interrupt::Error(
+                        state.file_path.clone(),
+                        line,
+                        format!("There is no field with name {name}"),
+                    ))?,
                 }
-                NodeKind::As(pattern_node, alias) => match pattern_node.data.deref() {
-                    NodeKind::Pattern(PatternKind::Prototype, node) => {
-                        Pattern::PtA(execute(state, node.clone())?, alias.clone())
-                    }
-                    NodeKind::Pattern(PatternKind::Equalness, node) => {
-                        Pattern::EqA(execute(state, node.clone())?, alias.clone())
-                    }
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
-            };
-            state.define_method(
-                state.contexts.last().unwrap().0,
-                pattern,
-                Body::Do(body.clone()),
-            );
-            Ok(state.contexts.last().unwrap().0)
-        }
-        NodeKind::Import(name, node) => {
-            // TODO: Put here dir of the executed file
-            let target_object_ptr = execute(state, node.clone())?;
-            let current_dir_path = PathBuf::from(unsafe { CURRENT_FILE_PATH.clone() })
-                .parent()
-                .unwrap()
-                .to_owned();
-            import_module(
-                state,
-                target_object_ptr,
-                name.into(),
-                vec![LIB_DIR.into(), current_dir_path.to_str().unwrap().into()],
-            )
-        }
+            }
+            Op::DefineKw(name, body) => {
+                define(state, Pattern::Kw(name.clone()), &None, body)?;
+                here(state)?
+            }
+            Op::DefinePt(alias, guard, body) => {
+                let ptr = pop(&mut stack)?;
+                let pattern = match alias {
+                    Some(alias) => Pattern::PtA(ptr, alias.clone()),
+                    None => Pattern::Pt(ptr),
+                };
+                define(state, pattern, guard, body)?;
+                here(state)?
+            }
+            Op::DefineEq(alias, guard, body) => {
+                let ptr = pop(&mut stack)?;
+                let pattern = match alias {
+                    Some(alias) => Pattern::EqA(ptr, alias.clone()),
+                    None => Pattern::Eq(ptr),
+                };
+                define(state, pattern, guard, body)?;
+                here(state)?
+            }
+            Op::Import(name) => {
+                let target_object_ptr = pop(&mut stack)?;
+                let dirs = module_dirs(&state.file_path, &state.lib_dir);
+                import_module(state, target_object_ptr, name.into(), dirs)?
+            }
+            Op::Error(message) => Err(Interrupt::Error(
+                state.file_path.clone(),
+                line,
+                message.clone(),
+            ))?,
+        };
+        stack.push(answer);
+    }
+    pop(&mut stack)
+}
+
+fn define(
+    state: &mut State,
+    pattern: Pattern,
+    guard: &Option<Rc<Code>>,
+    body: &Rc<Code>,
+) -> Result<(), Interrupt> {
+    let pattern = match guard {
+        Some(guard) => Pattern::Guarded(Box::new(pattern), guard.clone()),
+        None => pattern,
+    };
+    state.define_method(here(state)?, pattern, Body::Do(body.clone()));
+    Ok(())
+}
+
+/// Pop the operand of the op; compiled code doesn't pop more, than it pushes.
+fn pop(stack: &mut Vec<usize>) -> Result<usize, Interrupt> {
+    stack
+        .pop()
+        .ok_or_else(|| Interrupt::Err("Fatal system error: Operand stack is empty".into()))
+}
+
+/// Contexts, that are seen by a block: names are looked up in the contexts
+/// down to the innermost method context and then in the global context.
+fn captured_contexts(contexts: &[(usize, bool)]) -> Vec<(usize, bool)> {
+    match contexts
+        .iter()
+        .rposition(|(_, is_for_method)| *is_for_method)
+    {
+        Some(0) | None => contexts.to_vec(),
+        Some(index) => [&contexts[..1], &contexts[index..]].concat(),
     }
 }
 
-fn execute_queue(state: &mut State, queue: &Vec<Node>, line: usize) -> Result<usize, Interrupt> {
-    if queue.len() == 0 {
+/// The innermost context; contexts are left only by the code, that entered them.
+fn here(state: &State) -> Result<usize, Interrupt> {
+    state.here().ok_or_else(no_context)
+}
+
+fn global_context(state: &State) -> Result<usize, Interrupt> {
+    state
+        .contexts
+        .first()
+        .map(|context| context.0)
+        .ok_or_else(no_context)
+}
+
+fn no_context() -> Interrupt {
+    Interrupt::Err("Fatal system error: Context object does not exist".into())
+}
+
+/// Get field or call keyword-method of the context-object.
+fn load_name(state: &mut State, name: &str, line: usize) -> Result<usize, Interrupt> {
+    let some_method = state.get_method_ctx(name.into());
+    let context = match state.contexts.last() {
+        Some(c) => c.0,
+        None => Err(Interrupt::Error(
+            state.file_path.clone(),
+            line,
+            format!("There is no field or key-method named `{name}'"),
+        ))?,
+    };
+    if let Some(method) = some_method {
+        // Try call method of the context-object
+        let name = match method.1.unguarded() {
+            Pattern::Kw(_) | Pattern::Eq(_) | Pattern::Pt(_) => "[[no as]]".into(),
+            Pattern::EqA(_, name) | Pattern::PtA(_, name) => name.clone(),
+            Pattern::Guarded(..) => unreachable!(),
+        };
+        let method = method.clone();
+        call_method(state, context, method, (name, context), false)
+    } else if let Some(value) = state.get_field_value_ctx(name.into()) {
+        // Try get field of a context-object
+        match value {
+            Value::Pointer(ptr) => Ok(ptr),
+            Value::Int(_) | Value::Float(_) => Err(Interrupt::Error(
+                state.file_path.clone(),
+                line,
+                format!("Field `{name}' holds a system value, that is not an object"),
+            )),
+        }
+    } else if let Some(answer) = selector_not_understood(state, context, name, line)? {
+        Ok(answer)
+    } else {
         Err(Interrupt::Error(
-            unsafe { CURRENT_FILE_PATH.clone() },
+            state.file_path.clone(),
             line,
-            "Empty block of code".into(),
+            format!(
+                "Undefined keyword-method or field name: {}{}",
+                name,
+                suggest(&state.names_ctx(), name)
+            ),
         ))?
     }
-    let mut result = 0;
-    for node in queue {
-        match execute(state, node.clone()) {
-            Ok(ptr) => result = ptr,
-            Err(int) => Err(int)?,
+}
+
+/// Call the fallback method for the keyword, if it is not a name of the context.
+/// Fail, if the recipient has no fallback method.
+fn keyword_not_understood(
+    state: &mut State,
+    recipient: usize,
+    name: &str,
+    line: usize,
+) -> Result<Option<usize>, Interrupt> {
+    if state.get_method_ctx(name.into()).is_some()
+        || state.get_field_value_ctx(name.into()).is_some()
+    {
+        return Ok(None);
+    }
+    match selector_not_understood(state, recipient, name, line)? {
+        Some(answer) => Ok(Some(answer)),
+        None => Err(no_keyword_method(state, recipient, name, line)),
+    }
+}
+
+/// Call the fallback method with the selector of the keyword; answer None, if there is no fallback.
+fn selector_not_understood(
+    state: &mut State,
+    recipient: usize,
+    name: &str,
+    line: usize,
+) -> Result<Option<usize>, Interrupt> {
+    if state
+        .get_method(recipient, "doesNotUnderstand".into())
+        .is_none()
+    {
+        return Ok(None);
+    }
+    let selector = new_selector(state, name)?;
+    does_not_understand(state, recipient, selector, line)
+}
+
+fn no_keyword_method(state: &State, recipient: usize, name: &str, line: usize) -> Interrupt {
+    Interrupt::Error(
+        state.file_path.clone(),
+        line,
+        format!(
+            "{} has no keyword-method `{name}'{}",
+            state.describe(recipient),
+            suggest(&state.keywords_of(recipient), name)
+        ),
+    )
+}
+
+/// Suggest the most similar names for a mistyped one.
+fn suggest(names: &[String], name: &str) -> String {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut similar = names
+        .iter()
+        .map(|other| (edit_distance(name, other), other))
+        .filter(|(distance, other)| *distance <= max_distance && *other != name)
+        .collect::<Vec<_>>();
+    similar.sort();
+    similar.dedup_by(|a, b| a.1 == b.1);
+    match similar.first() {
+        Some((best_distance, _)) => {
+            let best = similar
+                .iter()
+                .filter(|(distance, _)| distance == best_distance)
+                .map(|(_, other)| format!("`{other}'"))
+                .collect::<Vec<_>>();
+            format!(". Did you mean {}?", best.join(" or "))
+        }
+        None => String::new(),
+    }
+}
+
+/// Levenshtein distance between the strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Call the fallback method of the recipient like `recipient doesNotUnderstand (message)`.
+/// Answer None, if the recipient has no fallback method.
+fn does_not_understand(
+    state: &mut State,
+    recipient: usize,
+    message: usize,
+    line: usize,
+) -> Result<Option<usize>, Interrupt> {
+    let method = match state.get_method(recipient, "doesNotUnderstand".into()) {
+        Some(method) => method.clone(),
+        None => return Ok(None),
+    };
+    let arg = ("[:doesNotUnderstand]".into(), recipient);
+    let handler = call_method(state, recipient, method, arg, false)?;
+    let method = match match_method(state, handler, message, line)? {
+        Some(method) => method,
+        None => Err(Interrupt::Error(
+            state.file_path.clone(),
+            line,
+            format!(
+                "Fallback method of {} does not take message {}",
+                state.describe(recipient),
+                state.describe(message)
+            ),
+        ))?,
+    };
+    let name = arg_name(&method.1, "[[no as]]");
+    call_method(state, handler, method, (name, message), false).map(Some)
+}
+
+/// Create an object of the keyword message.
+pub fn new_selector(state: &mut State, name: &str) -> Result<usize, Interrupt> {
+    let ptr = copy_global(state, "Selector")?;
+    state.selectors.push((ptr, name.into()));
+    Ok(ptr)
+}
+
+/// Send the keyword message from a native method.
+pub fn send_keyword(state: &mut State, recipient: usize, name: &str) -> Result<usize, Interrupt> {
+    if let Some(method) = state.get_method(recipient, name.into()) {
+        let method = method.clone();
+        return call_method(
+            state,
+            recipient,
+            method,
+            (format!("[:{name}]"), recipient),
+            false,
+        );
+    }
+    match selector_not_understood(state, recipient, name, 0)? {
+        Some(answer) => Ok(answer),
+        None => Err(no_keyword_method(state, recipient, name, 0)),
+    }
+}
+
+/// Send the message object from a native method.
+pub fn send_message(
+    state: &mut State,
+    recipient: usize,
+    message: usize,
+) -> Result<usize, Interrupt> {
+    send(state, &SendCache::default(), recipient, message, 0, false)
+}
+
+/// Match method of the recipient by the message and call it.
+fn send(
+    state: &mut State,
+    cache: &SendCache,
+    recipient: usize,
+    message: usize,
+    line: usize,
+    is_tail: bool,
+) -> Result<usize, Interrupt> {
+    let method = match lookup_method(state, cache, recipient, message, line)? {
+        Some(method) => method,
+        None => match does_not_understand(state, recipient, message, line)? {
+            Some(answer) => return Ok(answer),
+            None => Err(Interrupt::Error(
+                state.file_path.clone(),
+                line,
+                format!(
+                    "Failed to match method of {} for message {}",
+                    state.describe(recipient),
+                    state.describe(message)
+                ),
+            ))?,
+        },
+    };
+    let name = arg_name(&method.1, "[[no as]]");
+    call_method(state, recipient, method, (name, message), is_tail)
+}
+
+/// Get the parent of the running method's owner, where `super' starts method lookup.
+fn super_ptr(state: &State, line: usize) -> Result<usize, Interrupt> {
+    let owner = state.running_method_owner();
+    let message = match owner.map(|owner| state.definition_owner(owner)) {
+        Some(0) => "`super' is used in a method of Object, that has no parent",
+        Some(owner) => match state.parent(owner) {
+            Some(parent) => return Ok(parent),
+            None => "`super' is used in a method of a removed object",
+        },
+        None => "`super' is used outside of a method",
+    };
+    Err(Interrupt::Error(
+        state.file_path.clone(),
+        line,
+        message.into(),
+    ))
+}
+
+/// Match method of the parent prototype by the message and call it for the recipient.
+fn super_send(
+    state: &mut State,
+    super_ptr: usize,
+    recipient: usize,
+    message: usize,
+    line: usize,
+    is_tail: bool,
+) -> Result<usize, Interrupt> {
+    let method = match find_method(state, super_ptr, recipient, message, line, &mut false)? {
+        Some(method) => method,
+        None => Err(Interrupt::Error(
+            state.file_path.clone(),
+            line,
+            format!(
+                "Failed to match super method of {} for message {}",
+                state.describe(recipient),
+                state.describe(message)
+            ),
+        ))?,
+    };
+    let name = arg_name(&method.1, "[[no as]]");
+    call_method(state, recipient, method, (name, message), is_tail)
+}
+
+/// Call the method; a call in the tail of a method body replaces the running method,
+/// so it doesn't take the call depth.
+fn call_method(
+    state: &mut State,
+    recipient: usize,
+    method: Method,
+    arg: (String, usize),
+    is_tail: bool,
+) -> Result<usize, Interrupt> {
+    if let (true, Body::Do(_)) = (is_tail, &method.2) {
+        return Err(Interrupt::TailCall(recipient, Box::new(method), arg));
+    }
+    let (method_owner, _, body, file_path) = method;
+    execute_method(state, recipient, method_owner, body, arg, file_path)
+}
+
+pub fn new_int(state: &mut State, value: isize) -> Result<usize, Interrupt> {
+    let ptr = copy_global(state, "Int")?;
+    state.let_field(ptr, "value".into(), Value::Int(value));
+    Ok(ptr)
+}
+
+pub fn new_string(state: &mut State, text: &str) -> Result<usize, Interrupt> {
+    let ptr = copy_global(state, "String")?;
+    state.strings.insert(ptr, text.into());
+    Ok(ptr)
+}
+
+/// Text of the string, that the object answers to `toString'.
+pub fn to_text(state: &mut State, ptr: usize) -> Result<String, Interrupt> {
+    let answer = send_keyword(state, ptr, "toString")?;
+    match state.text_of(answer) {
+        Some(text) => Ok(text.clone()),
+        None => Err(Interrupt::Err(format!(
+            "`toString' of {} answered {}, that is not a string",
+            state.describe(ptr),
+            state.describe(answer)
+        ))),
+    }
+}
+
+/// Get the object of the global context, e.g. `True' or `None'.
+pub fn global(state: &State, name: &str) -> Result<usize, Interrupt> {
+    match state.get_field_value(1, name.into()) {
+        Some(Value::Pointer(ptr)) => Ok(ptr),
+        _ => Err(Interrupt::Err(format!(
+            "There is no global object `{name}'"
+        ))),
+    }
+}
+
+fn copy_global(state: &mut State, name: &str) -> Result<usize, Interrupt> {
+    let prototype = global(state, name)?;
+    state
+        .copy(prototype)
+        .ok_or_else(|| Interrupt::Err(format!("Failed to copy global object `{name}'")))
+}
+
+fn loop_answer(
+    state: &State,
+    stack: &mut Vec<usize>,
+    has_answer: bool,
+) -> Result<usize, Interrupt> {
+    match has_answer {
+        true => pop(stack),
+        false => global(state, "None"),
+    }
+}
+
+/// `break' and `continue' leave only loops, whose bodies are written in the same method,
+/// so they are scoped by the innermost method context, or by the global context outside of methods.
+pub fn loop_scope(contexts: &[(usize, bool)]) -> Option<usize> {
+    contexts
+        .iter()
+        .rev()
+        .find(|(_, is_for_method)| *is_for_method)
+        .or(contexts.first())
+        .map(|(ptr, _)| *ptr)
+}
+
+/// Run code of the block object in a new copy of its captured context.
+/// The argument is bound to the parameter of the block.
+pub fn run_block(
+    state: &mut State,
+    block_ptr: usize,
+    arg: Option<usize>,
+) -> Result<usize, Interrupt> {
+    let block = match state.blocks.iter().find(|b| b.0 == block_ptr) {
+        Some(block) => block.clone(),
+        None => Err(Interrupt::Error(
+            state.file_path.clone(),
+            0,
+            format!("{} is not a block", state.describe(block_ptr)),
+        ))?,
+    };
+    let (_, parameter, code, contexts, file_path) = block;
+    // Objects created by the run are not held by the running code out of it.
+    let first_ptr = state.op_count;
+    let arg = match (parameter, arg) {
+        (Some(name), Some(ptr)) => Some((name, ptr)),
+        (Some(name), None) => Some((name, global(state, "None")?)),
+        (None, None) => None,
+        (None, Some(_)) => Err(Interrupt::Error(
+            state.file_path.clone(),
+            0,
+            "The block does not take an argument".into(),
+        ))?,
+    };
+    let super_contexts = std::mem::replace(&mut state.contexts, contexts);
+    let super_file_path = state.file_path.clone();
+    state.file_path = file_path;
+
+    let sub_context = match state
+        .contexts
+        .last()
+        .map(|context| context.0)
+        .and_then(|context| state.copy(context))
+    {
+        Some(sub_context) => sub_context,
+        None => {
+            state.file_path = super_file_path;
+            state.contexts = super_contexts;
+            return Err(Interrupt::Err(format!(
+                "Context of {} does not exist",
+                state.describe(block_ptr)
+            )));
         }
+    };
+    state.contexts.push((sub_context, false));
+    if let Some((name, ptr)) = arg {
+        state.let_field(sub_context, name, Value::Pointer(ptr));
+    }
+    let result = run(state, &code);
+
+    state.file_path = super_file_path;
+    state.contexts = super_contexts;
+    if !is_error(&result) {
+        state.clear_garbage_since(first_ptr, &answered_objects(&result));
     }
-    Ok(result)
+    result
 }
 
 pub fn execute_method(
     state: &mut State,
-    owner_ptr: usize,
+    recipient: usize,
+    method_owner: usize,
     body: Body,
     arg: (String, usize),
     file_path: String,
 ) -> Result<usize, Interrupt> {
-    let context = {
-        state.objects.iter().find(|obj| obj.0 == owner_ptr).unwrap();
-        let new_ptr = state.op_count;
-        state.op_count += 1;
-        state.objects.push((new_ptr, owner_ptr, owner_ptr));
-        new_ptr
-    };
-    let super_file_path = unsafe { CURRENT_FILE_PATH.clone() };
-    unsafe { CURRENT_FILE_PATH = file_path }
-
-    state.contexts.push((context, true));
-    state.let_field(context, arg.0, Value::Pointer(arg.1));
-    let result = loop {
-        let result = match body {
-            Body::Do(ref body) => execute(state, body.clone()),
-            Body::Rust(body_func) => body_func(state),
+    enter_call(state)?;
+    let super_file_path = state.file_path.clone();
+    let contexts_len = state.contexts.len();
+    let method_owners_len = state.method_owners.len();
+    // Objects created by the call are not held by the running code out of it.
+    let first_ptr = state.op_count;
+
+    let (mut recipient, mut method_owner, mut body, mut arg, mut file_path) =
+        (recipient, method_owner, body, arg, file_path);
+    // Contexts of the methods, that are replaced by tail calls, but may be captured by blocks.
+    let mut replaced_contexts = Vec::new();
+    let result = 'call: loop {
+        if state.parent(recipient).is_none() {
+            break 'call Err(Interrupt::Err(format!(
+                "Object #{recipient} does not exist"
+            )));
+        }
+        let context = {
+            let new_ptr = state.op_count;
+            state.op_count += 1;
+            state.objects.push((new_ptr, recipient, recipient));
+            new_ptr
         };
-        match result {
-            Ok(ptr) => break Ok(ptr),
-            Err(Interrupt::Return(ptr)) => break Ok(ptr),
-            Err(Interrupt::Repeat) => continue,
-            Err(int) => Err(int)?,
+        state.file_path = file_path;
+        state.contexts.push((context, true));
+        state.method_owners.push((context, method_owner));
+        state.let_field(context, arg.0, Value::Pointer(arg.1));
+        loop {
+            let result = match body {
+                Body::Do(ref code) => run_code(state, code, true),
+                Body::Rust(body_func) => {
+                    state.native_contexts.push(context);
+                    let result = body_func(state);
+                    state.native_contexts.pop();
+                    // Objects made by native methods belong to the caller,
+                    // as if the caller copied them itself.
+                    if let Some(&(caller, _)) = state.contexts.get(contexts_len.wrapping_sub(1)) {
+                        state.move_objects(context, context, caller);
+                    }
+                    result
+                }
+            };
+            match result {
+                Ok(ptr) => break 'call Ok(ptr),
+                // Returns from blocks leave the method, where the blocks are created.
+                Err(Interrupt::Return(ptr, target))
+                    if target == context || replaced_contexts.contains(&target) =>
+                {
+                    break 'call Ok(ptr)
+                }
+                Err(Interrupt::Repeat) => continue,
+                Err(Interrupt::Break(_, scope) | Interrupt::Continue(_, scope))
+                    if scope == context || replaced_contexts.contains(&scope) =>
+                {
+                    break 'call Err(Interrupt::Err(
+                        "`break' or `continue' is used outside of a loop".into(),
+                    ))
+                }
+                Err(Interrupt::TailCall(tail_recipient, tail_method, tail_arg)) => {
+                    if let Body::Do(code) = &body {
+                        if code.ops.iter().any(|op| matches!(op, Op::Block(..))) {
+                            replaced_contexts.push(context);
+                        }
+                    }
+                    state.contexts.truncate(contexts_len);
+                    let finished = state.method_owners.get(method_owners_len).copied();
+                    state.method_owners.truncate(method_owners_len);
+                    // The replaced method is left, so its objects are garbage, unless they are passed on.
+                    let white_list = [tail_recipient, tail_arg.1, tail_method.0];
+                    state.clear_garbage_since(first_ptr, &white_list);
+                    keep_part_owner(state, finished);
+                    replaced_contexts.retain(|ptr| state.parent(*ptr).is_some());
+                    (recipient, arg) = (tail_recipient, tail_arg);
+                    (method_owner, _, body, file_path) = *tail_method;
+                    continue 'call;
+                }
+                Err(int) => break 'call Err(int),
+            }
         }
     };
-    state.contexts.pop().unwrap();
+    state.contexts.truncate(contexts_len);
+    let finished = state.method_owners.get(method_owners_len).copied();
+    state.method_owners.truncate(method_owners_len);
+    if !is_error(&result) {
+        state.clear_garbage_since(first_ptr, &answered_objects(&result));
+    }
+    keep_part_owner(state, finished);
+    state.call_depth -= 1;
 
-    unsafe { CURRENT_FILE_PATH = super_file_path }
+    state.file_path = super_file_path;
 
     result
 }
 
+/// Contexts of finished methods are kept by the sub-contexts, that they answer,
+/// so later parts of multi-part methods can find the owner of the method for `super'.
+fn keep_part_owner(state: &mut State, finished: Option<(usize, usize)>) {
+    if let Some((context, owner)) = finished {
+        if state.parent(context).is_some() {
+            state.part_owners.insert(context, owner);
+        }
+    }
+}
+
+/// Objects of failed calls are collected once by the call, that handles the error,
+/// instead of on every level, that the error passes.
+fn is_error(result: &Result<usize, Interrupt>) -> bool {
+    matches!(result, Err(Interrupt::Err(_) | Interrupt::Error(..)))
+}
+
+/// Objects, that are carried out of the running code by its answer or interrupt.
+fn answered_objects(result: &Result<usize, Interrupt>) -> Vec<usize> {
+    match result {
+        Ok(ptr)
+        | Err(
+            Interrupt::Exit(ptr)
+            | Interrupt::Return(ptr, _)
+            | Interrupt::Break(ptr, _)
+            | Interrupt::Continue(ptr, _),
+        ) => vec![*ptr],
+        Err(Interrupt::TailCall(recipient, method, (_, arg))) => vec![*recipient, method.0, *arg],
+        Err(Interrupt::Repeat | Interrupt::Err(_) | Interrupt::Error(..)) => vec![],
+    }
+}
+
+/// Match method using the cache of the sending site.
+fn lookup_method(
+    state: &mut State,
+    cache: &SendCache,
+    recipient: usize,
+    message: usize,
+    line: usize,
+) -> Result<Option<Method>, Interrupt> {
+    let (recipient_key, message_key) =
+        match (state.method_owner(recipient), state.pattern_key(message)) {
+            (Some(r), Some(m)) => (r, m),
+            _ => return Ok(None),
+        };
+    if let Some(method) = state.cached_send(cache, recipient_key, message_key) {
+        return Ok(Some(method));
+    }
+    let mut is_cacheable = true;
+    let method = find_method(
+        state,
+        recipient_key,
+        recipient,
+        message,
+        line,
+        &mut is_cacheable,
+    )?;
+    if let (true, Some(method)) = (is_cacheable, &method) {
+        state.cache_send(cache, recipient_key, message_key, method.clone());
+    }
+    Ok(method)
+}
+
 pub fn match_method(
     state: &mut State,
     ptr: usize,
     message: usize,
-) -> Option<(usize, Pattern, Body, String)> {
-    for (owner_ptr, pattern, body, fp) in state.methods.clone().iter() {
-        match pattern {
-            Pattern::Eq(pattern_ptr) | Pattern::EqA(pattern_ptr, ..)
-                if *owner_ptr == ptr && {
-                    // pattern_ptr == message
-                    let method = state.get_method(*pattern_ptr, "==".into()).unwrap();
-                    let ptr = execute_method(
-                        state,
-                        *pattern_ptr,
-                        method.2.clone(),
-                        ("[[no as *MM]]".into(), 0),
-                        "<std>".into(),
-                    )
-                    .unwrap();
-
-                    let method = match_method(state, ptr, message).unwrap();
-                    let arg_name = match &method.1 {
-                        Pattern::Eq(_) | Pattern::Pt(_) => "[[no as *MM2]]".into(),
-                        Pattern::EqA(_, name) | Pattern::PtA(_, name) => name.clone(),
-                        _ => unreachable!(),
-                    };
-                    let result_ptr = execute_method(
-                        state,
-                        ptr,
-                        method.2.clone(),
-                        (arg_name, message),
-                        method.3.clone(),
-                    );
-
-                    result_ptr.unwrap()
-                        == state
-                            .get_field_value(1, "True".into())
-                            .unwrap()
-                            .unwrap_ptr()
-                } =>
-            {
-                return Some((*owner_ptr, pattern.clone(), body.clone(), fp.clone()));
+    line: usize,
+) -> Result<Option<Method>, Interrupt> {
+    find_method(state, ptr, ptr, message, line, &mut false)
+}
+
+/// Name of the argument of a method matched by the pattern.
+fn arg_name(pattern: &Pattern, default: &str) -> String {
+    match pattern.unguarded() {
+        Pattern::EqA(_, name) | Pattern::PtA(_, name) => name.clone(),
+        Pattern::Eq(_) | Pattern::Pt(_) => default.into(),
+        Pattern::Kw(_) | Pattern::Guarded(..) => unreachable!(),
+    }
+}
+
+/// Find the most specific method of the nearest owner in the parent chain.
+/// Equalness-patterns are preferred over prototype-patterns,
+/// prototype-patterns are ranked by relation of the message to the prototype,
+/// guarded patterns are preferred over the same patterns without guard.
+/// Matching is not cacheable, if an equalness-pattern or a guard was checked.
+fn find_method(
+    state: &mut State,
+    ptr: usize,
+    recipient: usize,
+    message: usize,
+    line: usize,
+    is_cacheable: &mut bool,
+) -> Result<Option<Method>, Interrupt> {
+    let methods = state
+        .methods
+        .iter()
+        .filter(|method| method.0 == ptr)
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut best: Option<((usize, bool), Method)> = None;
+    let mut is_ambiguous = false;
+    for method in methods {
+        let rank = match method.1.unguarded() {
+            Pattern::Eq(pattern_ptr) | Pattern::EqA(pattern_ptr, ..) => {
+                *is_cacheable = false;
+                match is_equal(state, *pattern_ptr, message, line)? {
+                    true => Some(0),
+                    false => None,
+                }
             }
-            Pattern::Pt(pattern_ptr) | Pattern::PtA(pattern_ptr, ..)
-                if *owner_ptr == ptr && state.relation(message, pattern_ptr.clone()).is_some() =>
-            {
-                return Some((*owner_ptr, pattern.clone(), body.clone(), fp.clone()))
+            Pattern::Pt(pattern_ptr) | Pattern::PtA(pattern_ptr, ..) => state
+                .relation(message, *pattern_ptr)
+                .map(|distance| distance + 1),
+            Pattern::Kw(_) | Pattern::Guarded(..) => None,
+        };
+        let rank = match (rank, &method.1) {
+            (Some(rank), Pattern::Guarded(pattern, guard)) => {
+                *is_cacheable = false;
+                let arg = (arg_name(pattern, "[[no as]]"), message);
+                let body = Body::Do(guard.clone());
+                let answer =
+                    execute_method(state, recipient, method.0, body, arg, method.3.clone())?;
+                match answer == global(state, "True")? {
+                    true => Some((rank, false)),
+                    false => None,
+                }
+            }
+            (rank, _) => rank.map(|rank| (rank, true)),
+        };
+        match (rank, &best) {
+            (None, _) => (),
+            (Some(rank), Some((best_rank, _))) if rank == *best_rank => is_ambiguous = true,
+            (Some(rank), Some((best_rank, _))) if rank > *best_rank => (),
+            (Some(rank), _) => {
+                best = Some((rank, method));
+                is_ambiguous = false;
             }
-            _ => continue,
         }
     }
-    match ptr {
-        0 => None,
-        _ => match_method(state, state.parent(ptr)?, message),
+    if is_ambiguous {
+        Err(Interrupt::Error(
+            state.file_path.clone(),
+            line,
+            format!(
+                "Ambiguous method match of {} for message {}: several patterns are equally specific",
+                state.describe(recipient),
+                state.describe(message)
+            ),
+        ))?
+    }
+    match (best, ptr) {
+        (Some((_, method)), _) => Ok(Some(method)),
+        (None, 0) => Ok(None),
+        (None, _) => match state.parent(ptr) {
+            Some(parent) => find_method(state, parent, recipient, message, line, is_cacheable),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Count the call, that recurses in the executor: a send, a guard or a comparison.
+fn enter_call(state: &mut State) -> Result<(), Interrupt> {
+    if state.call_depth >= state.max_call_depth {
+        return Err(Interrupt::Err(format!(
+            "Maximum call depth of {} is exceeded",
+            state.max_call_depth
+        )));
     }
+    state.call_depth += 1;
+    Ok(())
+}
+
+/// Check `pattern_ptr == message'.
+/// The comparison counts as a call, because matching its answer may compare again.
+fn is_equal(
+    state: &mut State,
+    pattern_ptr: usize,
+    message: usize,
+    line: usize,
+) -> Result<bool, Interrupt> {
+    enter_call(state)?;
+    let result = compare(state, pattern_ptr, message, line);
+    state.call_depth -= 1;
+    result
+}
+
+fn compare(
+    state: &mut State,
+    pattern_ptr: usize,
+    message: usize,
+    line: usize,
+) -> Result<bool, Interrupt> {
+    let method = match state.get_method(pattern_ptr, "==".into()) {
+        Some(method) => method,
+        None => Err(Interrupt::Error(
+            state.file_path.clone(),
+            line,
+            format!("{} has no keyword-method `=='", state.describe(pattern_ptr)),
+        ))?,
+    };
+    let ptr = execute_method(
+        state,
+        pattern_ptr,
+        method.0,
+        method.2.clone(),
+        ("[[no as *MM]]".into(), 0),
+        "<std>".into(),
+    )?;
+
+    let method = match match_method(state, ptr, message, line)? {
+        Some(method) => method,
+        None => Err(Interrupt::Error(
+            state.file_path.clone(),
+            line,
+            format!(
+                "Equality of {} does not take message {}",
+                state.describe(pattern_ptr),
+                state.describe(message)
+            ),
+        ))?,
+    };
+    let arg_name = arg_name(&method.1, "[[no as *MM2]]");
+    let result_ptr = execute_method(
+        state,
+        ptr,
+        method.0,
+        method.2.clone(),
+        (arg_name, message),
+        method.3.clone(),
+    )?;
+
+    Ok(result_ptr == global(state, "True")?)
 }
 
 pub fn import_module(
@@ -428,32 +1116,26 @@ pub fn import_module(
     let result = None;
 
     // Try to find Proba-module
-    let mut file_path = None;
-    for d in &dirs {
-        let fp = format!("{d}/{module_name}.proba");
-        if std::path::Path::new(&fp).is_file() {
-            file_path = Some(fp);
-            break;
-        }
-    }
-    let result = match file_path {
+    let result = match find_module(&module_name, &dirs) {
         Some(file_path) => match crate::parser::parse_file(file_path.clone()) {
             Ok(tokens) => {
-                let tree_node = crate::lexer::lex(tokens);
-                let super_file_path = unsafe { CURRENT_FILE_PATH.clone() };
-                unsafe { CURRENT_FILE_PATH = file_path }
+                let super_file_path = state.file_path.clone();
+                let tree_node = crate::lexer::lex(tokens, &file_path);
+                state.file_path = file_path;
                 let result = execute(state, tree_node);
-                unsafe { CURRENT_FILE_PATH = super_file_path }
+                state.file_path = super_file_path;
                 result
             }
             Err(_) => Err(Interrupt::Err(format!(
                 "Import error: There is no method with name `{module_name}'."
             ))),
         },
-        None if result.is_some() => result.unwrap(),
-        None => Err(Interrupt::Err(format!(
-            "Import error: There is no method with name `{module_name}'."
-        ))),
+        None => match result {
+            Some(result) => result,
+            None => Err(Interrupt::Err(format!(
+                "Import error: There is no method with name `{module_name}'."
+            ))),
+        },
     };
 
     // Restore context stack
@@ -461,3 +1143,200 @@ pub fn import_module(
 
     result
 }
+
+/// Directories, where modules imported by the file are searched: the library and the file's one.
+pub fn module_dirs(file_path: &str, lib_dir: &str) -> Vec<String> {
+    let current_dir_path = match PathBuf::from(file_path).parent() {
+        Some(path) if !path.as_os_str().is_empty() => path.to_string_lossy().into_owned(),
+        _ => ".".into(),
+    };
+    vec![lib_dir.into(), current_dir_path]
+}
+
+/// Path of the first module file with the name in the directories.
+pub fn find_module(module_name: &str, dirs: &[String]) -> Option<String> {
+    dirs.iter()
+        .map(|d| format!("{d}/{module_name}.proba"))
+        .find(|fp| std::path::Path::new(fp).is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probastd::tests::state_with_buffer;
+    use crate::rpmt::exec;
+
+    fn error_message(result: Result<usize, Interrupt>) -> String {
+        match result {
+            Err(Interrupt::Error(_, _, message) | Interrupt::Err(message)) => message,
+            result => panic!("Expected an error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn guards_and_comparisons_count_as_calls() {
+        let (mut state, _) = state_with_buffer();
+        state.max_call_depth = 10;
+
+        let guard = "let D copy Object; at D on Int as n if (D n) do 1; D 0;";
+        assert_eq!(
+            error_message(exec(&mut state, guard)),
+            "Maximum call depth of 10 is exceeded"
+        );
+        let comparison = "let E copy Object; at E on : == do E; at E on = E do 1; E E;";
+        assert_eq!(
+            error_message(exec(&mut state, comparison)),
+            "Maximum call depth of 10 is exceeded"
+        );
+        assert_eq!(state.call_depth, 0);
+    }
+
+    #[test]
+    fn blocks_capture_the_method_and_global_contexts() {
+        let contexts = [(1, false), (5, true), (7, false), (9, true), (11, false)];
+        assert_eq!(
+            captured_contexts(&contexts),
+            vec![(1, false), (9, true), (11, false)]
+        );
+        assert_eq!(captured_contexts(&contexts[..1]), vec![(1, false)]);
+
+        let (mut state, buffer) = state_with_buffer();
+        let code = "let x 7; let R copy Object;
+            at R on Int as n do (n == 3) then block (x) else block (block (R (n ++)) value);
+            (R 0) print;";
+        exec(&mut state, code).unwrap();
+        assert_eq!(buffer.borrow().as_str(), "7");
+    }
+
+    #[test]
+    fn super_starts_at_the_parent_of_the_method_owner() {
+        let (mut state, buffer) = state_with_buffer();
+        let code = "let A copy Object; at A on : one do 1; at A on : add; Int as n do n;
+            let B copy A; at B on : one do (super one) ++;
+            at B on : add; Int as n do (super add n) ++;
+            let C copy B;
+            B one print; (B add 5) print; (C add 5) print;";
+        exec(&mut state, code).unwrap();
+        assert_eq!(buffer.borrow().as_str(), "266");
+    }
+
+    #[test]
+    fn most_specific_pattern_is_matched() {
+        let (mut state, buffer) = state_with_buffer();
+        let code = "let M copy Object;
+            at M on Object do \"object \"; at M on Number do \"number \"; at M on Int do \"int \";
+            at M on = 3 do \"three \"; at M on Int as n if (n == 4) do \"four \";
+            (M 5) print; (M 3) print; (M 4) print; (M \"text\") print;
+            at M on = 3 do \"new three\"; (M 3) print;";
+        exec(&mut state, code).unwrap();
+        assert_eq!(buffer.borrow().as_str(), "int three four object new three");
+    }
+
+    #[test]
+    fn equally_specific_patterns_are_ambiguous() {
+        let (mut state, buffer) = state_with_buffer();
+        let ambiguous = "Ambiguous method match of A (Object) for message #";
+        let code = "let A copy Object; at A on = 3 do 1; at A on Int if True do 2; (A 3) print;";
+        exec(&mut state, code).unwrap();
+        assert_eq!(buffer.borrow().as_str(), "1");
+
+        exec(
+            &mut state,
+            "at A on = 3 as n if True do 3; at A on = 3 if (3 == 3) do 4;",
+        )
+        .unwrap();
+        assert!(error_message(exec(&mut state, "A 3;")).starts_with(ambiguous));
+        let code = "at A on Int as a if True do 5; at A on Int as b if (b == b) do 6;";
+        exec(&mut state, code).unwrap();
+        assert!(error_message(exec(&mut state, "A 5;")).starts_with(ambiguous));
+    }
+
+    #[test]
+    fn cached_sends_follow_method_changes() {
+        let (mut state, buffer) = state_with_buffer();
+        let code = "let A copy Object; at A on : name do \"a\"; at A on Int do \"int\";
+            let B copy A; let Show copy Object;
+            at Show on : run do ((B name) + \" \" + (B 1) + \";\") print;
+            Show run; at B on : name do \"b\"; Show run;
+            at B on Number do \"number\"; Show run;
+            at B on : name do \"c\"; at B on = 1 do \"one\"; Show run;
+            at A on : name do \"d\"; Show run;";
+        exec(&mut state, code).unwrap();
+        assert_eq!(
+            buffer.borrow().as_str(),
+            "a int;b int;b number;c one;c one;"
+        );
+
+        // Compiled codes and their caches are shared with snapshots of the state.
+        let snapshot = state.clone();
+        exec(&mut state, "at B on : name do \"e\"; Show run;").unwrap();
+        state.clone_from(&snapshot);
+        exec(&mut state, "Show run;").unwrap();
+        assert!(buffer.borrow().ends_with("e one;c one;"));
+    }
+
+    #[test]
+    fn return_leaves_the_method_with_the_value() {
+        let (mut state, buffer) = state_with_buffer();
+        let code = "let M copy Object; at M on Int as n do (return (n ++); 0); (M 1) print;
+            at M on : self do (return; 0); ((M self) == M) print;
+            at M on : find do (5 times block as i ((i == 3) then block (return i) else block (i)); 0);
+            M find print;";
+        exec(&mut state, code).unwrap();
+        assert_eq!(buffer.borrow().as_str(), "2[[True]]3");
+
+        assert_eq!(
+            error_message(exec(&mut state, "return 2;")),
+            "`return' is used outside of a method"
+        );
+        let result = exec(
+            &mut state,
+            "at M on : keep do block (return 1); (M keep) value;",
+        );
+        assert!(matches!(result, Err(Interrupt::Return(..))));
+    }
+
+    #[test]
+    fn unmatched_messages_are_given_to_the_fallback_method() {
+        let (mut state, buffer) = state_with_buffer();
+        let code = "let Target copy Object; at Target on : name do \"target\";
+            at Target on Int as n do (n ++);
+            let P copy Object; at P on : doesNotUnderstand; Object as message do (message sendTo: Target);
+            let Q copy Object; at Q on : doesNotUnderstand; Int as n do n;
+            P name print; (P 4) print; (Q 7) print;";
+        exec(&mut state, code).unwrap();
+        assert_eq!(buffer.borrow().as_str(), "target57");
+
+        assert_eq!(
+            error_message(exec(&mut state, "Q other;")),
+            "Fallback method of Q (Object) does not take message [:other] (Selector < Object)"
+        );
+        assert_eq!(
+            error_message(exec(&mut state, "Target other;")),
+            "Target (Object) has no keyword-method `other'"
+        );
+    }
+
+    #[test]
+    fn similar_names_are_suggested() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("λx", "λy"), 1);
+        let names = ["ab", "ac", "abcd", "ad"].map(String::from);
+        assert_eq!(suggest(&names, "ad"), ". Did you mean `ab' or `ac'?");
+        assert_eq!(
+            suggest(&names, "ae"),
+            ". Did you mean `ab' or `ac' or `ad'?"
+        );
+        assert_eq!(suggest(&names, "xyz"), "");
+
+        let (mut state, _) = state_with_buffer();
+        exec(&mut state, "let counter 1;").unwrap();
+        assert_eq!(
+            error_message(exec(&mut state, "countr;")),
+            "Undefined keyword-method or field name: countr. Did you mean `counter'?"
+        );
+        assert!(error_message(exec(&mut state, "\"a\" sise;"))
+            .ends_with("has no keyword-method `sise'. Did you mean `size'?"));
+    }
+}
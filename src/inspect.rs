@@ -0,0 +1,122 @@
+// Readable report of an object: its prototypes, creation context, fields and methods.
+use std::collections::HashSet;
+
+use crate::vmstate::{Body, Pattern, State, Value};
+
+/// Nested objects deeper than this are only described.
+const MAX_DEPTH: usize = 8;
+
+pub fn inspect(state: &State, ptr: usize) -> String {
+    let mut out = String::new();
+    let mut shown = HashSet::new();
+    inspect_object(state, ptr, 0, &mut shown, &mut out);
+    out
+}
+
+fn inspect_object(
+    state: &State,
+    ptr: usize,
+    depth: usize,
+    shown: &mut HashSet<usize>,
+    out: &mut String,
+) {
+    let indent = "  ".repeat(depth * 2);
+    shown.insert(ptr);
+    out.push_str(&format!("{}\n", state.describe(ptr)));
+    out.push_str(&format!("{indent}  pointer: #{ptr}\n"));
+    out.push_str(&format!("{indent}  parents: {}\n", parents(state, ptr)));
+    let context = match state.context_of(ptr) {
+        Some(1) => "#1 (global context)".into(),
+        Some(context_ptr) => state.describe(context_ptr),
+        None => "none".into(),
+    };
+    out.push_str(&format!("{indent}  context: {context}\n"));
+    if let Some(text) = state.strings.get(&ptr) {
+        out.push_str(&format!("{indent}  text: {text:?}\n"));
+    }
+
+    let fields = state
+        .fields
+        .iter()
+        .filter(|field| field.0 == ptr)
+        .collect::<Vec<_>>();
+    if !fields.is_empty() {
+        out.push_str(&format!("{indent}  fields:\n"));
+    }
+    for (_, name, value) in fields {
+        out.push_str(&format!("{indent}    {name} = "));
+        match value {
+            Value::Int(value) => out.push_str(&format!("{value}\n")),
+            Value::Float(value) => out.push_str(&format!("{value}\n")),
+            Value::Pointer(field_ptr) if shown.contains(field_ptr) => {
+                out.push_str(&format!("{} (shown above)\n", state.describe(*field_ptr)))
+            }
+            Value::Pointer(field_ptr) if depth < MAX_DEPTH && is_expandable(state, *field_ptr) => {
+                inspect_object(state, *field_ptr, depth + 1, shown, out)
+            }
+            Value::Pointer(field_ptr) => out.push_str(&format!("{}\n", state.describe(*field_ptr))),
+        }
+    }
+
+    let methods = state
+        .methods
+        .iter()
+        .filter(|method| method.0 == ptr)
+        .collect::<Vec<_>>();
+    if !methods.is_empty() {
+        out.push_str(&format!("{indent}  methods:\n"));
+    }
+    for (_, pattern, body, file) in methods {
+        let body = match body {
+            Body::Rust(_) => "native".to_string(),
+            Body::Do(_) => format!("in `{file}'"),
+        };
+        out.push_str(&format!(
+            "{indent}    {} ({body})\n",
+            pattern_text(state, pattern)
+        ));
+    }
+}
+
+/// Prototypes of the object from the nearest one.
+fn parents(state: &State, ptr: usize) -> String {
+    let mut parents = Vec::new();
+    let mut visited = HashSet::from([ptr]);
+    let mut ptr = ptr;
+    while ptr != 0 {
+        ptr = match state.parent(ptr) {
+            Some(parent_ptr) if visited.insert(parent_ptr) => parent_ptr,
+            _ => break,
+        };
+        parents.push(match state.name_of(ptr) {
+            Some(name) => format!("{name} #{ptr}"),
+            None => format!("#{ptr}"),
+        });
+    }
+    if parents.is_empty() {
+        return "none".into();
+    }
+    parents.join(" < ")
+}
+
+/// Kind of the pattern with its keyword or its object.
+fn pattern_text(state: &State, pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Kw(keyword) => format!("keyword {keyword}"),
+        Pattern::Eq(ptr) => format!("equal to {}", state.describe(*ptr)),
+        Pattern::EqA(ptr, name) => format!("equal to {} as {name}", state.describe(*ptr)),
+        Pattern::Pt(ptr) => format!("copy of {}", state.describe(*ptr)),
+        Pattern::PtA(ptr, name) => format!("copy of {} as {name}", state.describe(*ptr)),
+        Pattern::Guarded(pattern, _) => format!("{} if guarded", pattern_text(state, pattern)),
+    }
+}
+
+/// Only anonymous objects without a value are shown inside of their owner.
+fn is_expandable(state: &State, ptr: usize) -> bool {
+    state.name_of(ptr).is_none()
+        && state.text_of(ptr).is_none()
+        && !matches!(
+            state.get_field_value(ptr, "value".into()),
+            Some(Value::Int(_))
+        )
+}
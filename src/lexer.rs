@@ -1,4 +1,4 @@
-use std::{ops::Deref, process::exit};
+use std::{ops::Deref, process::exit, rc::Rc};
 
 use crate::parser::{Token, TokenKind};
 
@@ -13,8 +13,11 @@ pub enum PatternKind {
 pub enum NodeKind {
     Here,
     Me,
-    Return,
+    Super,
+    Return(Option<Node>),
     Repeat,
+    Break(Option<Node>),
+    Continue(Option<Node>),
     Name(String),
     Int(isize),
     Float(f64),
@@ -25,40 +28,84 @@ pub enum NodeKind {
     QuickContext(Vec<Node>),
     Message(Node, Node),
     Copy(Node),
+    Block(Option<String>, Node),
     Import(String, Node),
     At(Node, Node),
     Let(String, Node),
     Set(String, Node),
-    OnDo(Vec<Node>, Node),
-    // NOTE: Maybe useless
-    // OnRust(
-    //     Vec<Node>,
-    //     unsafe extern fn(State) -> (State, Result<usize, Interrupt>),
-    // ),
-    // TODO: Add keyword `import` or sth like that.
+    OnDo(Vec<(Node, Option<Node>)>, Node), // ((pattern, guard), body)
+                                           // NOTE: Maybe useless
+                                           // OnRust(
+                                           //     Vec<Node>,
+                                           //     unsafe extern fn(State) -> (State, Result<usize, Interrupt>),
+                                           // ),
+                                           // TODO: Add keyword `import` or sth like that.
 }
 
+/// Immutable node of the syntax tree; clones share the same data.
 #[derive(Debug, Clone)]
 pub struct Node {
-    pub data: Box<NodeKind>,
+    pub data: Rc<NodeKind>,
     pub line: usize,
 }
 
 impl Node {
     pub fn new(data: NodeKind, line: usize) -> Self {
         Self {
-            data: Box::new(data),
+            data: Rc::new(data),
             line,
         }
     }
+
+    /// Direct subnodes, including patterns and guards of method definitions.
+    pub fn children(&self) -> Vec<&Node> {
+        match self.data.deref() {
+            NodeKind::Here
+            | NodeKind::Me
+            | NodeKind::Super
+            | NodeKind::Repeat
+            | NodeKind::Name(_)
+            | NodeKind::Int(_)
+            | NodeKind::Float(_)
+            | NodeKind::String(_) => vec![],
+            NodeKind::Return(node) | NodeKind::Break(node) | NodeKind::Continue(node) => {
+                node.iter().collect()
+            }
+            NodeKind::Pattern(_, node)
+            | NodeKind::As(node, _)
+            | NodeKind::Copy(node)
+            | NodeKind::Block(_, node)
+            | NodeKind::Import(_, node)
+            | NodeKind::Let(_, node)
+            | NodeKind::Set(_, node) => vec![node],
+            NodeKind::Queue(nodes) | NodeKind::QuickContext(nodes) => nodes.iter().collect(),
+            NodeKind::Message(a, b) | NodeKind::At(a, b) => vec![a, b],
+            NodeKind::OnDo(patterns, body) => {
+                let mut nodes = Vec::new();
+                for (pattern, guard) in patterns {
+                    nodes.push(pattern);
+                    nodes.extend(guard);
+                }
+                nodes.push(body);
+                nodes
+            }
+        }
+    }
 }
 
-pub fn lex(tokens: Vec<Token>) -> Node {
+/// Syntax errors are reported in the file with the path.
+pub fn lex(tokens: Vec<Token>, file_path: &str) -> Node {
     let mut i = 0;
-    lex_queue(&tokens, &mut i, 0, true)
+    lex_queue(&tokens, &mut i, file_path, 0, true)
 }
 
-fn lex_queue(tokens: &Vec<Token>, i: &mut usize, line: usize, global: bool) -> Node {
+fn lex_queue(
+    tokens: &Vec<Token>,
+    i: &mut usize,
+    file_path: &str,
+    line: usize,
+    global: bool,
+) -> Node {
     let mut queue = vec![];
 
     while *i < tokens.len() {
@@ -66,18 +113,23 @@ fn lex_queue(tokens: &Vec<Token>, i: &mut usize, line: usize, global: bool) -> N
             TokenKind::EOQ => {
                 *i += 1;
             }
-            TokenKind::As | TokenKind::Do => {
-                syntax_error(line, "Unexpected method definition keyword.".into());
+            TokenKind::As | TokenKind::If | TokenKind::Do => {
+                syntax_error(
+                    file_path,
+                    line,
+                    "Unexpected method definition keyword.".into(),
+                );
             }
             TokenKind::CloseParen | TokenKind::CloseContext if global => {
                 syntax_error(
+                    file_path,
                     line,
                     "Unexpected closing paren or brace in global context.".into(),
                 );
             }
             TokenKind::CloseParen | TokenKind::CloseContext => break,
             _ => {
-                let node = match lex_message_chain(tokens, i) {
+                let node = match lex_message_chain(tokens, i, file_path) {
                     Some(n) => n,
                     None => continue,
                 };
@@ -88,16 +140,16 @@ fn lex_queue(tokens: &Vec<Token>, i: &mut usize, line: usize, global: bool) -> N
     Node::new(NodeKind::Queue(queue), line)
 }
 
-fn lex_message_chain(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
-    let recipient = lex_singleton(tokens, i)?;
+fn lex_message_chain(tokens: &Vec<Token>, i: &mut usize, file_path: &str) -> Option<Node> {
+    let recipient = lex_singleton(tokens, i, file_path)?;
     let line = recipient.line;
-    let mut message = match lex_singleton(tokens, i) {
+    let mut message = match lex_singleton(tokens, i, file_path) {
         Some(node) => Node::new(NodeKind::Message(recipient, node), line),
         None => return Some(recipient),
     };
 
     while *i < tokens.len() {
-        match lex_singleton(tokens, i) {
+        match lex_singleton(tokens, i, file_path) {
             Some(node) => message = Node::new(NodeKind::Message(message, node), line),
             None => break,
         }
@@ -105,13 +157,14 @@ fn lex_message_chain(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
     return Some(message);
 }
 
-fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
+fn lex_singleton(tokens: &Vec<Token>, i: &mut usize, file_path: &str) -> Option<Node> {
     let token = tokens.get(*i)?;
     let node = match &token.data {
         TokenKind::EOQ
         | TokenKind::CloseParen
         | TokenKind::CloseContext
         | TokenKind::As
+        | TokenKind::If
         | TokenKind::Do => None?,
         TokenKind::Here => {
             *i += 1;
@@ -121,14 +174,38 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
             *i += 1;
             Node::new(NodeKind::Me, token.line)
         }
+        TokenKind::Super => {
+            *i += 1;
+            Node::new(NodeKind::Super, token.line)
+        }
         TokenKind::Return => {
+            // "return" [MESSAGE_CHAIN]
             *i += 1;
-            Node::new(NodeKind::Return, token.line)
+            Node::new(
+                NodeKind::Return(lex_message_chain(tokens, i, file_path)),
+                token.line,
+            )
         }
         TokenKind::Repeat => {
             *i += 1;
             Node::new(NodeKind::Repeat, token.line)
         }
+        TokenKind::Break => {
+            // "break" [MESSAGE_CHAIN]
+            *i += 1;
+            Node::new(
+                NodeKind::Break(lex_message_chain(tokens, i, file_path)),
+                token.line,
+            )
+        }
+        TokenKind::Continue => {
+            // "continue" [MESSAGE_CHAIN]
+            *i += 1;
+            Node::new(
+                NodeKind::Continue(lex_message_chain(tokens, i, file_path)),
+                token.line,
+            )
+        }
         TokenKind::Name(name) => {
             *i += 1;
             let data = if name == "here" {
@@ -152,10 +229,10 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
         }
         TokenKind::OpenParen => {
             *i += 1;
-            let queue = lex_queue(tokens, i, token.line, false);
+            let queue = lex_queue(tokens, i, file_path, token.line, false);
             let t = match tokens.get(*i) {
                 Some(t) => &t.data,
-                None => syntax_error(token.line, "Paren is never closed".into()),
+                None => syntax_error(file_path, token.line, "Paren is never closed".into()),
             };
             match t {
                 TokenKind::CloseParen => {
@@ -163,20 +240,23 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
                     queue
                 }
                 TokenKind::CloseContext => {
-                    syntax_error(token.line, "Unexpected closing brace".into())
+                    syntax_error(file_path, token.line, "Unexpected closing brace".into())
                 }
                 t => unreachable!("Unexpected token: {:?}", t),
             }
         }
         TokenKind::OpenContext => {
             *i += 1;
-            let queue = match *lex_queue(tokens, i, token.line, false).data {
-                NodeKind::Queue(queue) => queue,
+            let queue = match lex_queue(tokens, i, file_path, token.line, false)
+                .data
+                .deref()
+            {
+                NodeKind::Queue(queue) => queue.clone(),
                 _ => unreachable!("UNREACHABLE"),
             };
             let token_kind = match tokens.get(*i) {
                 Some(val) => &val.data,
-                None => syntax_error(token.line, "Brace is never closed.".into()),
+                None => syntax_error(file_path, token.line, "Brace is never closed.".into()),
             };
             match token_kind {
                 TokenKind::CloseContext => {
@@ -184,7 +264,7 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
                     Node::new(NodeKind::QuickContext(queue), token.line)
                 }
                 TokenKind::CloseParen => {
-                    syntax_error(token.line, "Unexpected closing paren.".into())
+                    syntax_error(file_path, token.line, "Unexpected closing paren.".into())
                 }
                 t => unreachable!("UNREACHABLE: Unexpected token: {:?}.", t),
             }
@@ -192,78 +272,137 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
         TokenKind::Copy => {
             // "copy" SINGLETON EOQ
             *i += 1;
-            let data = NodeKind::Copy(match lex_singleton(tokens, i) {
+            let data = NodeKind::Copy(match lex_singleton(tokens, i, file_path) {
                 Some(val) => val,
-                None => syntax_error(token.line, "Unexpected end of copy-statement".into()),
+                None => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unexpected end of copy-statement".into(),
+                ),
             });
             Node::new(data, token.line)
         }
+        TokenKind::Block => {
+            // "block" ["as" NAME] SINGLETON
+            *i += 1;
+            let parameter = match tokens.get(*i).map(|t| &t.data) {
+                Some(TokenKind::As) => match tokens.get(*i + 1).map(|t| &t.data) {
+                    Some(TokenKind::Name(name)) => {
+                        *i += 2;
+                        Some(name.clone())
+                    }
+                    _ => syntax_error(
+                        file_path,
+                        token.line,
+                        "Expecting a name after token `as'.".into(),
+                    ),
+                },
+                _ => None,
+            };
+            let body = match lex_singleton(tokens, i, file_path) {
+                Some(val) => val,
+                None => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unexpected end of block-statement".into(),
+                ),
+            };
+            Node::new(NodeKind::Block(parameter, body), token.line)
+        }
         TokenKind::Import => {
             // "import" NAME SINGLETON
             *i += 1;
-            let node = match lex_singleton(tokens, i) {
+            let node = match lex_singleton(tokens, i, file_path) {
                 Some(val) => val,
-                None => syntax_error(token.line, "Unexpected end of import-statement".into()),
+                None => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unexpected end of import-statement".into(),
+                ),
             };
-            let name = match *node.data {
-                NodeKind::Name(name) => name,
+            let name = match node.data.deref() {
+                NodeKind::Name(name) => name.clone(),
                 _ => None?,
             };
-            let node = match lex_singleton(tokens, i) {
+            let node = match lex_singleton(tokens, i, file_path) {
                 Some(val) => val,
-                None => syntax_error(token.line, "Unexpected end of import-statement.".into()),
+                None => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unexpected end of import-statement.".into(),
+                ),
             };
             Node::new(NodeKind::Import(name, node), token.line)
         }
         TokenKind::Let => {
             // "let" NAME MESSAGE_CHAIN EOQ
             *i += 1;
-            let name = match &tokens[*i].data {
-                TokenKind::Name(name) => name,
-                _ => syntax_error(token.line, "Unexpected end of let-statement.".into()),
+            let name = match tokens.get(*i).map(|t| &t.data) {
+                Some(TokenKind::Name(name)) => name,
+                _ => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unexpected end of let-statement.".into(),
+                ),
             };
             *i += 1;
-            let node_data = match lex_message_chain(tokens, i) {
+            let node_data = match lex_message_chain(tokens, i, file_path) {
                 Some(node) => NodeKind::Let(name.clone(), node),
-                None => syntax_error(token.line, "Unexpected end of let-statement.".into()),
+                None => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unexpected end of let-statement.".into(),
+                ),
             };
             Node::new(node_data, token.line)
         }
         TokenKind::Set => {
             // "set" NAME MESSAGE_CHAIN EOQ
             *i += 1;
-            let name = match &tokens[*i].data {
-                TokenKind::Name(name) => name,
-                _ => syntax_error(token.line, "Name is expected after `set' keyword.".into()),
+            let name = match tokens.get(*i).map(|t| &t.data) {
+                Some(TokenKind::Name(name)) => name,
+                _ => syntax_error(
+                    file_path,
+                    token.line,
+                    "Name is expected after `set' keyword.".into(),
+                ),
             };
             *i += 1;
-            let node_data = match lex_message_chain(tokens, i) {
+            let node_data = match lex_message_chain(tokens, i, file_path) {
                 Some(node) => NodeKind::Set(name.clone(), node),
-                None => syntax_error(token.line, "Unexpected end of set-statement.".into()),
+                None => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unexpected end of set-statement.".into(),
+                ),
             };
             Node::new(node_data, token.line)
         }
         TokenKind::At => {
             // "at" SINGLETON MESSAGE_CHAIN EOQ
             *i += 1;
-            let context = match lex_singleton(tokens, i) {
+            let context = match lex_singleton(tokens, i, file_path) {
                 Some(val) => val,
-                None => syntax_error(token.line, "Expecting singleton message.".into()),
+                None => syntax_error(file_path, token.line, "Expecting singleton message.".into()),
             };
-            match lex_message_chain(tokens, i) {
+            match lex_message_chain(tokens, i, file_path) {
                 Some(node) => Node::new(NodeKind::At(context, node), token.line),
-                None => syntax_error(token.line, "Empty body of at-statement.".into()),
+                None => syntax_error(file_path, token.line, "Empty body of at-statement.".into()),
             }
         }
         TokenKind::On => {
-            // "on" {["="|":"] MESSAGE_CHAIN ["as" NAME] ";"} ("be"|"do") MESSAGE_CHAIN EOQ
+            // "on" {["="|":"] MESSAGE_CHAIN ["as" NAME] ["if" MESSAGE_CHAIN] ";"} ("be"|"do") MESSAGE_CHAIN EOQ
             *i += 1;
             // Patterns
             let mut patterns = vec![];
             while *i < tokens.len() {
                 let token_data = match tokens.get(*i) {
                     Some(val) => &val.data,
-                    None => syntax_error(token.line, "Unfinished method definition.".into()),
+                    None => syntax_error(
+                        file_path,
+                        token.line,
+                        "Unfinished method definition.".into(),
+                    ),
                 };
                 let pattern_kind = match token_data {
                     TokenKind::Name(n) if n.as_str() == ":" => {
@@ -271,9 +410,11 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
                         *i += 1;
                         let token = match tokens.get(*i) {
                             Some(val) => val,
-                            None => {
-                                syntax_error(token.line, "Unfinished method definition.".into())
-                            }
+                            None => syntax_error(
+                                file_path,
+                                token.line,
+                                "Unfinished method definition.".into(),
+                            ),
                         };
                         match &token.data {
                             TokenKind::Name(name) => {
@@ -282,11 +423,12 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
                                     PatternKind::Keyword,
                                     Node::new(data, token.line),
                                 );
-                                patterns.push(Node::new(data, token.line));
+                                patterns.push((Node::new(data, token.line), None));
                                 *i += 1;
                                 let token_kind = match tokens.get(*i) {
                                     Some(val) => &val.data,
                                     None => syntax_error(
+                                        file_path,
                                         token.line,
                                         "Unfinished method definition.".into(),
                                     ),
@@ -297,10 +439,11 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
                                         continue;
                                     }
                                     TokenKind::Do => break,
-                                    _ => syntax_error(token.line, format!("Expecting `;', or `do' after a keyword-pattern, but got: {token:?}")),
+                                    _ => syntax_error(file_path, token.line, format!("Expecting `;', or `do' after a keyword-pattern, but got: {token:?}")),
                                 }
                             }
                             _ => syntax_error(
+                                file_path,
                                 token.line,
                                 format!("Expecting a name after `:', but got {token:?}"),
                             ),
@@ -312,87 +455,90 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
                     }
                     _ => PatternKind::Prototype,
                 };
-                let pattern_message = match lex_message_chain(tokens, i) {
+                let pattern_message = match lex_message_chain(tokens, i, file_path) {
                     Some(val) => val,
-                    None => syntax_error(token.line, "Empty pattern message.".into()),
+                    None => syntax_error(file_path, token.line, "Empty pattern message.".into()),
                 };
-                let node = {
-                    match tokens.get(*i) {
+                let mut pattern_node = Node::new(
+                    NodeKind::Pattern(pattern_kind, pattern_message.clone()),
+                    pattern_message.line,
+                );
+                // Alias
+                if let Some(TokenKind::As) = tokens.get(*i).map(|t| &t.data) {
+                    *i += 1;
+                    let token = match tokens.get(*i) {
                         Some(val) => val,
-                        None => syntax_error(token.line, "Unfinished method definition.".into()),
-                    }
-                };
-                match node.data {
-                    TokenKind::As => {
-                        *i += 1;
-                        let token = match tokens.get(*i) {
-                            Some(val) => val,
-                            None => {
-                                syntax_error(token.line, "Unfinished method definition.".into())
-                            }
-                        };
-                        let name = if let TokenKind::Name(name) = &token.data {
-                            name.clone()
-                        } else {
-                            syntax_error(token.line, "Expecting a name after token `as'.".into())
-                        };
+                        None => syntax_error(
+                            file_path,
+                            token.line,
+                            "Unfinished method definition.".into(),
+                        ),
+                    };
+                    let name = if let TokenKind::Name(name) = &token.data {
+                        name.clone()
+                    } else {
+                        syntax_error(
+                            file_path,
+                            token.line,
+                            "Expecting a name after token `as'.".into(),
+                        )
+                    };
+                    *i += 1;
+                    pattern_node = Node::new(NodeKind::As(pattern_node, name), token.line);
+                }
+                // Guard
+                let guard = match tokens.get(*i).map(|t| &t.data) {
+                    Some(TokenKind::If) => {
                         *i += 1;
-                        let node_data = NodeKind::As(
-                            Node::new(
-                                NodeKind::Pattern(pattern_kind, pattern_message.clone()),
-                                pattern_message.line,
-                            ),
-                            name,
-                        );
-                        patterns.push(Node::new(node_data, token.line));
-                        let token_kind = match tokens.get(*i) {
-                            Some(val) => &val.data,
+                        match lex_message_chain(tokens, i, file_path) {
+                            Some(val) => Some(val),
                             None => {
-                                syntax_error(token.line, "Unfinished method definition.".into())
-                            }
-                        };
-                        match token_kind {
-                            TokenKind::EOQ => {
-                                *i += 1;
-                                continue;
+                                syntax_error(file_path, token.line, "Empty guard message.".into())
                             }
-                            TokenKind::Do => break,
-                            _ => syntax_error(
-                                token.line,
-                                "Expecting `;' or one of keywords `as' and `do'.".into(),
-                            ),
                         }
                     }
-                    TokenKind::EOQ => {
-                        patterns.push(Node::new(
-                            NodeKind::Pattern(pattern_kind, pattern_message),
-                            token.line,
-                        ));
+                    _ => None,
+                };
+                patterns.push((pattern_node, guard));
+                match tokens.get(*i).map(|t| &t.data) {
+                    Some(TokenKind::EOQ) => {
                         *i += 1;
                         continue;
                     }
-                    TokenKind::Do => {
-                        patterns.push(Node::new(
-                            NodeKind::Pattern(pattern_kind, pattern_message),
-                            token.line,
-                        ));
-                        break;
-                    }
-                    _ => syntax_error(
+                    Some(TokenKind::Do) => break,
+                    Some(_) => syntax_error(
+                        file_path,
+                        token.line,
+                        "Expecting `;' or one of keywords `as', `if' and `do'.".into(),
+                    ),
+                    None => syntax_error(
+                        file_path,
                         token.line,
-                        "Expecting `;' or one of keywords `as' and `do'".into(),
+                        "Unfinished method definition.".into(),
                     ),
                 }
             }
-            let token_data = &tokens[*i].data;
+            let token_data = match tokens.get(*i) {
+                Some(val) => &val.data,
+                None => syntax_error(
+                    file_path,
+                    token.line,
+                    "Unfinished method definition.".into(),
+                ),
+            };
             *i += 1;
             if patterns.is_empty() {
-                syntax_error(token.line, "Empty pattern in method definition.".into())
+                syntax_error(
+                    file_path,
+                    token.line,
+                    "Empty pattern in method definition.".into(),
+                )
             }
             // Body
-            let body_message = match lex_message_chain(tokens, i) {
+            let body_message = match lex_message_chain(tokens, i, file_path) {
                 Some(val) => val,
                 None => syntax_error(
+                    file_path,
                     token.line,
                     "Empty body message of method definition.".into(),
                 ),
@@ -412,9 +558,9 @@ fn lex_singleton(tokens: &Vec<Token>, i: &mut usize) -> Option<Node> {
 pub(crate) fn expand_method_definition(node_data: NodeKind, line: usize) -> NodeKind {
     match &node_data {
         NodeKind::OnDo(patterns, body) => {
-            /*  on A as a; B as b do [[something]];
+            /*  on A as a if (a ok?); B as b do [[something]];
              * =>
-             *  on A as a do {
+             *  on A as a if (a ok?) do {
              *      let a a;
              *      on B as b do [[something]];
              *      here
@@ -431,7 +577,7 @@ pub(crate) fn expand_method_definition(node_data: NodeKind, line: usize) -> Node
                 return node_data;
             }
             let mut queue_vec = Vec::new();
-            match patterns[0].data.deref() {
+            match patterns[0].0.data.deref() {
                 NodeKind::As(_, name) => {
                     let name_node = Node::new(NodeKind::Name(name.into()), line);
                     queue_vec.push(Node::new(NodeKind::Let(name.into(), name_node), line));
@@ -452,8 +598,8 @@ pub(crate) fn expand_method_definition(node_data: NodeKind, line: usize) -> Node
     }
 }
 
-fn syntax_error(line: usize, message: String) -> ! {
+fn syntax_error(file_path: &str, line: usize, message: String) -> ! {
     let line = line + 1;
-    println!("Syntax error on line {line}: {message}");
-    exit(0)
+    println!("Syntax error on line {line} in `{file_path}': {message}");
+    exit(1)
 }
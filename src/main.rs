@@ -1,75 +1,210 @@
 use executor::Interrupt;
 use std::io::Write;
 use std::process::exit;
-use std::{env, io};
+use std::{env, fs, io, thread};
 
-use crate::executor::CURRENT_FILE_PATH;
 use crate::rpmt::exec;
 
+mod compiler;
+mod dump;
 pub mod executor;
+mod inspect;
 mod lexer;
 mod parser;
 mod probastd;
 pub mod rpmt;
 pub mod vmstate;
+mod word_chars;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Config {
     pub file_path: Option<String>,
+    pub code: Option<String>,
     pub args: Vec<String>,
     pub interactive_terminal_mode: bool,
     pub debug_state: bool,
     pub debug_answer: bool,
     pub debug_context: bool,
+    pub max_call_depth: usize,
+    pub dump_tokens: bool,
+    pub dump_ast: bool,
+    pub dump_json: bool,
+    pub check: bool,
+    pub lib_dir: Option<String>,
 }
 
 impl Config {
     pub const fn new() -> Self {
         Self {
             file_path: Some(String::new()),
+            code: None,
             args: Vec::new(),
             interactive_terminal_mode: false,
             debug_state: false,
             debug_answer: false,
             debug_context: false,
+            max_call_depth: vmstate::DEFAULT_MAX_CALL_DEPTH,
+            dump_tokens: false,
+            dump_ast: false,
+            dump_json: false,
+            check: false,
+            lib_dir: None,
         }
     }
 }
 
-pub(crate) static mut PROG_CONFIG: Config = Config::new();
+/// Rust stack reserved for each method call, because calls recurse in the executor.
+/// A call takes about 3KB of the stack in release builds and about 32KB in debug builds.
+const CALL_STACK_SIZE: usize = if cfg!(debug_assertions) { 48 } else { 8 } * 1024;
+/// Deeper limits would reserve too much memory for the stack.
+const MAX_CALL_DEPTH: usize = 50_000;
 
 fn main() {
-    parse_args();
+    let config = parse_args();
+    let stack_size = config.max_call_depth * CALL_STACK_SIZE;
+    let interpreter = thread::Builder::new()
+        .stack_size(stack_size.max(8 * 1024 * 1024))
+        .spawn(move || run_program(&config));
+    match interpreter.map(|interpreter| interpreter.join()) {
+        Ok(Ok(_)) => (),
+        Ok(Err(_)) => exit(1),
+        Err(err) => {
+            println!("Failed to start interpreter thread: {err}");
+            exit(1)
+        }
+    }
+}
+
+fn run_program(config: &Config) {
+    if config.check {
+        check_program(config);
+    }
+    if config.dump_tokens || config.dump_ast {
+        dump_program(config);
+    }
     let mut state = vmstate::State::new();
+    state.max_call_depth = config.max_call_depth;
+    state.args = config.args.clone();
+    if let Some(lib_dir) = &config.lib_dir {
+        state.lib_dir = lib_dir.clone();
+    }
     match probastd::define_standard(&mut state) {
         Ok(_) => (),
         Err(int) => {
             println!("FATAL ERROR: Failed to load standard library!");
-            proba_exit(&mut state, Err(int));
+            proba_exit(config, &mut state, Err(int));
         }
     }
-    let file_path = if let Some(fp) = unsafe { PROG_CONFIG.file_path.clone() } {
-        fp
-    } else {
-        run_pit(&mut state);
+    let (file_path, source) = match program_source(config) {
+        Ok(Some(program)) => program,
+        Ok(None) => run_pit(config, &mut state),
+        Err(message) => {
+            println!("{message}");
+            exit(1)
+        }
     };
 
-    let tokens = match parser::parse_file(file_path.clone()) {
-        Ok(tokens) => tokens,
-        Err(_) => {
-            println!("Failed to open file `{file_path}'");
-            exit(0)
+    state.file_path = file_path;
+    let result = exec(&mut state, &source);
+    proba_exit(config, &mut state, result);
+}
+
+/// Name and text of the program given by `-e', by `-' for stdin or by a file path;
+/// None, if no program is given.
+fn program_source(config: &Config) -> Result<Option<(String, String)>, String> {
+    if let Some(code) = &config.code {
+        return Ok(Some(("<code>".into(), code.clone())));
+    }
+    let (file_path, contents) = match config.file_path.as_deref() {
+        None => return Ok(None),
+        Some("-") => ("<stdin>", io::read_to_string(io::stdin())),
+        Some(file_path) => (file_path, fs::read_to_string(file_path)),
+    };
+    match contents {
+        Ok(contents) => Ok(Some((file_path.into(), contents))),
+        Err(_) => Err(format!("Failed to open file `{file_path}'")),
+    }
+}
+
+/// Parse the program and the modules it imports without running them;
+/// exit with status 1 on the first error.
+fn check_program(config: &Config) -> ! {
+    let lib_dir = config.lib_dir.as_deref().unwrap_or(executor::LIB_DIR);
+    let mut pending = match program_source(config) {
+        Ok(Some(program)) => vec![program],
+        Ok(None) => {
+            println!("Expected program to check");
+            exit(1)
+        }
+        Err(message) => {
+            println!("{message}");
+            exit(1)
         }
     };
-    let tree = lexer::lex(tokens);
+    let mut checked: Vec<String> = Vec::new();
+    while let Some((file_path, source)) = pending.pop() {
+        if checked.contains(&file_path) {
+            continue;
+        }
+        let tree = lexer::lex(parser::parse_str(&source), &file_path);
 
-    unsafe { executor::CURRENT_FILE_PATH = PROG_CONFIG.file_path.clone().unwrap() };
-    let result = executor::execute(&mut state, tree);
-    proba_exit(&mut state, result);
+        let dirs = executor::module_dirs(&file_path, lib_dir);
+        let mut nodes = vec![&tree];
+        while let Some(node) = nodes.pop() {
+            if let lexer::NodeKind::Import(name, _) = node.data.as_ref() {
+                let line = node.line + 1;
+                let module_path = match executor::find_module(name, &dirs) {
+                    Some(module_path) => module_path,
+                    None => {
+                        println!("Import error on line {line} in `{file_path}': There is no module with name `{name}'");
+                        exit(1)
+                    }
+                };
+                match fs::read_to_string(&module_path) {
+                    Ok(module_source) => pending.push((module_path, module_source)),
+                    Err(_) => {
+                        println!("Import error on line {line} in `{file_path}': Failed to open file `{module_path}'");
+                        exit(1)
+                    }
+                }
+            }
+            nodes.extend(node.children());
+        }
+        checked.push(file_path);
+    }
+    println!("Checked {} files, no errors found", checked.len());
+    exit(0)
 }
 
-fn parse_args() {
-    let config = unsafe { &mut PROG_CONFIG };
+/// Print tokens and the syntax tree of the program instead of running it.
+fn dump_program(config: &Config) -> ! {
+    let (file_path, source) = match program_source(config) {
+        Ok(Some(program)) => program,
+        Ok(None) => {
+            println!("Expected program to dump");
+            exit(0)
+        }
+        Err(message) => {
+            println!("{message}");
+            exit(0)
+        }
+    };
+    let tokens = parser::parse_str(&source);
+    if config.dump_tokens {
+        println!(
+            "{}",
+            dump::dump_tokens(&tokens, config.dump_json).trim_end()
+        );
+    }
+    if config.dump_ast {
+        let tree = lexer::lex(tokens, &file_path);
+        println!("{}", dump::dump_ast(&tree, config.dump_json).trim_end());
+    }
+    exit(0)
+}
+
+fn parse_args() -> Config {
+    let mut config = Config::new();
     let mut args = env::args().collect::<Vec<String>>();
     args.remove(0);
 
@@ -81,6 +216,44 @@ fn parse_args() {
             "-debug-state" | "-ds" => config.debug_state = true,
             "-debug-answer" | "-da" => config.debug_answer = true,
             "-debug-context" | "-dc" => config.debug_context = true,
+            "-dump-tokens" | "-dt" => config.dump_tokens = true,
+            "-dump-ast" | "-dast" => config.dump_ast = true,
+            "-dump-json" | "-dj" => config.dump_json = true,
+            "-check" => config.check = true,
+            "-e" => {
+                args.remove(0);
+                config.code = match args.first() {
+                    Some(code) => Some(code.clone()),
+                    None => {
+                        println!("Expected code after `-e'");
+                        exit(0)
+                    }
+                };
+            }
+            "-max-depth" | "-md" => {
+                args.remove(0);
+                config.max_call_depth = match args.first().map(|arg| arg.parse()) {
+                    Some(Ok(depth)) if (1..=MAX_CALL_DEPTH).contains(&depth) => depth,
+                    Some(Ok(_)) => {
+                        println!("Maximum call depth must be from 1 to {MAX_CALL_DEPTH}");
+                        exit(0)
+                    }
+                    _ => {
+                        println!("Expected maximum call depth after `-max-depth'");
+                        exit(0)
+                    }
+                };
+            }
+            "-lib-dir" => {
+                args.remove(0);
+                config.lib_dir = match args.first() {
+                    Some(dir) => Some(dir.clone()),
+                    None => {
+                        println!("Expected library directory after `-lib-dir'");
+                        exit(0)
+                    }
+                };
+            }
             "--" => {
                 args.remove(0);
                 break;
@@ -90,110 +263,193 @@ fn parse_args() {
         args.remove(0);
     }
 
-    if args.len() == 0 {
+    // Arguments after the code of `-e' are given to the program.
+    if args.is_empty() || config.code.is_some() {
         config.file_path = None;
     } else {
         config.file_path = Some(args.remove(0));
     }
     config.args = args;
+    config
 }
 
-fn run_pit(state: &mut vmstate::State) -> ! {
-    unsafe {
-        PROG_CONFIG.file_path = Some("<pit>".into());
-        PROG_CONFIG.interactive_terminal_mode = false;
-    }
+fn run_pit(config: &Config, state: &mut vmstate::State) -> ! {
+    let config = Config {
+        file_path: Some("<pit>".into()),
+        interactive_terminal_mode: false,
+        ..config.clone()
+    };
+    state.file_path = "<pit>".into();
 
     // TODO: Define methods for quitting and getting answer of previous executed command.
 
-    println!("\nCall method [: exit] or press ctrl-c to exit.\n");
+    println!("\nCall method [: exit] or press ctrl-c to exit.");
+    println!("Type `:undo' to revert the previous command, `:save <file>' to save the session,");
+    println!("`:inspect <expression>' to show the fields and methods of its answer.\n");
 
+    let mut history: Vec<PitEntry> = Vec::new();
     let mut command_input = String::new();
     let result = 'main: loop {
         print!("pit> ");
-        io::stdout().flush().unwrap();
+        let _ = io::stdout().flush();
         command_input.clear();
-        io::stdin()
-            .read_line(&mut command_input)
-            .expect("Failed to get user input"); // TODO: Handle error
+        match io::stdin().read_line(&mut command_input) {
+            Ok(0) => break 'main Ok(0),
+            Ok(_) => (),
+            Err(err) => {
+                println!("Failed to get user input: {err}");
+                break 'main Ok(0);
+            }
+        }
+        if run_pit_command(state, command_input.trim(), &mut history) {
+            continue;
+        }
+        history.push(PitEntry {
+            snapshot: Some(state.clone()),
+            command: command_input.trim_end().into(),
+            succeeded: false,
+        });
+        // Old commands are kept for `:save', but their states are dropped.
+        if history.len() > MAX_UNDO_DEPTH {
+            let index = history.len() - MAX_UNDO_DEPTH - 1;
+            history[index].snapshot = None;
+        }
+        let first_ptr = state.op_count;
         let result = exec(state, &command_input);
+        if let Ok(_) | Err(Interrupt::Exit(_)) = &result {
+            history.last_mut().unwrap().succeeded = true;
+        }
         match &result {
-            Ok(answer) => {
-                print!("=> ");
-                let method = state.get_method(*answer, "println".into()).unwrap();
-                let file_path = unsafe { CURRENT_FILE_PATH.clone() };
-                let res = executor::execute_method(
-                    state,
-                    *answer,
-                    method.2.clone(),
-                    ("[:println]".into(), *answer),
-                    file_path,
-                );
-                match res {
-                    Ok(_) => (),
-                    Err(int) => {
-                        println!("Failed to represent the answer:");
-                        if let Interrupt::Error(fp, line, message) = int {
-                            print_error(&fp, line, &message);
-                        }
-                    }
-                }
-            }
+            Ok(answer) => print_answer(state, *answer),
             Err(Interrupt::Exit(answer)) => {
-                print!("=> ");
-                let method = state.get_method(*answer, "println".into()).unwrap();
-                let file_path = unsafe { CURRENT_FILE_PATH.clone() };
-                let res = executor::execute_method(
-                    state,
-                    *answer,
-                    method.2.clone(),
-                    ("[:println]".into(), *answer),
-                    file_path,
-                );
-                match res {
-                    Ok(_) => (),
-                    Err(int) => {
-                        println!("Failed to represent the answer:");
-                        if let Interrupt::Error(fp, line, message) = int {
-                            print_error(&fp, line, &message);
-                        }
-                    }
-                }
+                print_answer(state, *answer);
                 break 'main result;
             }
-            Err(Interrupt::Error(fp, line, message)) => print_error(&fp, *line, message),
-            _ => todo!("handle other interrupts in PIT"), // TODO
+            Err(int) => {
+                print_interrupt(&state.file_path, int);
+                // Failed calls leave their objects to the caller, that handles the error.
+                state.clear_garbage_since(first_ptr, &[]);
+            }
         }
     };
 
-    proba_exit(state, result);
+    proba_exit(&config, state, result);
+}
+
+/// Print the answer of a PIT command with its `println' method.
+fn print_answer(state: &mut vmstate::State, answer: usize) {
+    match executor::to_text(state, answer) {
+        Ok(text) => println!("=> {text}"),
+        Err(int) => {
+            println!("Failed to represent the answer:");
+            print_interrupt(&state.file_path, &int);
+        }
+    }
+}
+
+/// Number of the last PIT commands, that can be undone.
+const MAX_UNDO_DEPTH: usize = 100;
+
+/// A PIT command together with the state it was executed on;
+/// None, if the command is too old to be undone.
+struct PitEntry {
+    snapshot: Option<vmstate::State>,
+    command: String,
+    succeeded: bool,
 }
 
-fn proba_exit(state: &mut vmstate::State, result: Result<usize, Interrupt>) -> ! {
-    if unsafe { PROG_CONFIG.debug_state } {
+/// Return true, if the input is a PIT command and it was handled.
+fn run_pit_command(state: &mut vmstate::State, input: &str, history: &mut Vec<PitEntry>) -> bool {
+    let (command, argument) = match input.split_once(char::is_whitespace) {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (input, ""),
+    };
+    match command {
+        ":undo" => match history.pop() {
+            Some(PitEntry {
+                snapshot: Some(snapshot),
+                command,
+                ..
+            }) => {
+                state.clone_from(&snapshot);
+                println!("Undone: {command}");
+            }
+            Some(entry) => {
+                history.push(entry);
+                println!("Nothing to undo.");
+            }
+            None => println!("Nothing to undo."),
+        },
+        ":save" if argument.is_empty() => println!("Usage: :save <file>"),
+        ":save" => {
+            // Failed commands are skipped, so the saved file can be replayed as a script.
+            let mut transcript = String::new();
+            for entry in history.iter().filter(|entry| entry.succeeded) {
+                transcript.push_str(&entry.command);
+                transcript.push('\n');
+            }
+            match fs::write(argument, transcript) {
+                Ok(_) => println!("Session is saved to `{argument}'"),
+                Err(err) => println!("Failed to save session to `{argument}': {err}"),
+            }
+        }
+        ":inspect" if argument.is_empty() => println!("Usage: :inspect <expression>"),
+        ":inspect" => {
+            // Inspection doesn't change the session.
+            let snapshot = state.clone();
+            match exec(state, argument) {
+                Ok(answer) | Err(Interrupt::Exit(answer)) => {
+                    print!("{}", inspect::inspect(state, answer))
+                }
+                Err(int) => print_interrupt(&state.file_path, &int),
+            }
+            state.clone_from(&snapshot);
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn proba_exit(config: &Config, state: &mut vmstate::State, result: Result<usize, Interrupt>) -> ! {
+    if config.debug_state {
         dbg!(&state);
     }
-    if unsafe { !PROG_CONFIG.debug_state && PROG_CONFIG.debug_context } {
+    if !config.debug_state && config.debug_context {
         dbg!(&state.contexts);
     }
 
     let answer = match result {
-        Ok(a) | Err(Interrupt::Exit(a) | Interrupt::Return(a)) => a,
-        Err(Interrupt::Error(fp, line, message)) => {
-            print_error(&fp, line, &message);
-            exit(0);
+        Ok(a) | Err(Interrupt::Exit(a)) => a,
+        Err(int) => {
+            print_interrupt(&state.file_path, &int);
+            exit(1);
         }
-        _ => unreachable!(),
     };
-    if unsafe { PROG_CONFIG.debug_answer } {
+    if config.debug_answer {
         println!("\nProgram returned: {answer}");
     }
 
-    if unsafe { PROG_CONFIG.interactive_terminal_mode } {
-        run_pit(state);
+    if config.interactive_terminal_mode {
+        run_pit(config, state);
     }
 
-    exit(0)
+    exit(state.exit_code)
+}
+
+/// Report an error or an interrupt, that has escaped its loop or method.
+fn print_interrupt(file_path: &str, int: &Interrupt) {
+    let message = match int {
+        Interrupt::Error(fp, line, message) => return print_error(fp, *line, message),
+        Interrupt::Err(message) => message.as_str(),
+        Interrupt::Exit(_) => return,
+        Interrupt::Return(..) => "`return' is used after its method has returned",
+        Interrupt::Repeat => "`repeat' is used outside of a method",
+        Interrupt::TailCall(..) => "Tail call is made outside of a method",
+        Interrupt::Break(..) | Interrupt::Continue(..) => {
+            "`break' or `continue' is used outside of a loop"
+        }
+    };
+    println!("\nRuntime error in `{file_path}':\n {message}");
 }
 
 fn print_error(file_path: &str, line: usize, message: &str) {
@@ -1,6 +1,6 @@
 use std::fs;
 
-use regex::Regex;
+use crate::word_chars::is_word_char;
 
 #[derive(Debug, Clone)]
 pub enum TokenKind {
@@ -15,6 +15,7 @@ pub enum TokenKind {
     EOQ,
     Here,
     Me,
+    Super,
     Copy,
     At,
     Let,
@@ -22,9 +23,13 @@ pub enum TokenKind {
     On,
     Do,
     As,
+    If,
     Return, // NOTE: I've just realized, that it may be useless
     Repeat,
+    Break,
+    Continue,
     Import,
+    Block,
 }
 
 #[derive(Debug, Clone)]
@@ -39,16 +44,15 @@ pub fn parse_file(file_path: String) -> Result<Vec<Token>, std::io::Error> {
 }
 
 pub fn parse_str(string: &str) -> Vec<Token> {
-    let token_re =
-        Regex::new(r#"(\[\[[^\]]*\]\]|"[^"]+"|[(){};]|[\w~!@#$%^&*\-+=:|\\,.\/<>?]+|\[|\]|\s+|.)"#)
-            .unwrap();
-    let string_re = Regex::new(r#"^"[^"]+"$"#).unwrap();
-    let comment_re = Regex::new(r#"^\[\[[^\]]*\]\]$"#).unwrap();
-
     let mut tokens = Vec::new();
     let mut line_count = 0usize;
-    for cap in token_re.captures_iter(&string) {
-        let token = match &cap[0] {
+    let mut rest = skip_shebang(string);
+    while let Some(first) = rest.chars().next() {
+        let (lexeme, tail) = rest.split_at(lexeme_length(rest, first));
+        rest = tail;
+        line_count += lexeme.matches('\n').count();
+
+        let token = match lexeme {
             "(" => TokenKind::OpenParen,
             ")" => TokenKind::CloseParen,
             "{" => TokenKind::OpenContext,
@@ -56,6 +60,7 @@ pub fn parse_str(string: &str) -> Vec<Token> {
             ";" => TokenKind::EOQ,
             "here" => TokenKind::Here,
             "me" => TokenKind::Me,
+            "super" => TokenKind::Super,
             "copy" => TokenKind::Copy,
             "at" => TokenKind::At,
             "let" => TokenKind::Let,
@@ -63,21 +68,19 @@ pub fn parse_str(string: &str) -> Vec<Token> {
             "on" | "[" => TokenKind::On,
             "do" | "]" => TokenKind::Do,
             "as" => TokenKind::As,
+            "if" => TokenKind::If,
             "return" => TokenKind::Return,
             "repeat" => TokenKind::Repeat,
+            "break" => TokenKind::Break,
+            "continue" => TokenKind::Continue,
             "import" => TokenKind::Import,
+            "block" => TokenKind::Block,
+            // Comments are the only longer lexemes starting with `[[',
+            // and strings are the only longer lexemes starting with `"'.
+            s if s.trim().is_empty() || s.starts_with("[[") => continue,
+            s if s.len() > 1 && s.starts_with('"') => TokenKind::String(s[1..s.len() - 1].into()),
             s => {
-                for c in s.chars() {
-                    if c == '\n' {
-                        line_count += 1;
-                    }
-                }
-
-                if s.trim().is_empty() || comment_re.is_match(s) {
-                    continue;
-                } else if string_re.is_match(s) {
-                    TokenKind::String(s[1..s.len() - 1].into())
-                } else if let Ok(i) = s.parse::<isize>() {
+                if let Ok(i) = s.parse::<isize>() {
                     TokenKind::Int(i)
                 } else if let Ok(f) = s.parse::<f64>() {
                     TokenKind::Float(f)
@@ -92,5 +95,173 @@ pub fn parse_str(string: &str) -> Vec<Token> {
         });
     }
 
-    return tokens;
+    tokens
+}
+
+/// Skip the `#!' line at the start of a script, but keep its newline for line numbers.
+fn skip_shebang(string: &str) -> &str {
+    match string.starts_with("#!") {
+        true => &string[string.find('\n').unwrap_or(string.len())..],
+        false => string,
+    }
+}
+
+/// Length in bytes of the lexeme at the start of the string, that starts with the char.
+/// Lexemes are comments `[[...]]`, non-empty strings, runs of name chars or of whitespaces,
+/// and single other chars.
+fn lexeme_length(string: &str, first: char) -> usize {
+    let after_first = &string[first.len_utf8()..];
+    match first {
+        '[' if after_first.starts_with('[') => match after_first[1..].find(']') {
+            Some(end) if after_first[1 + end + 1..].starts_with(']') => 2 + end + 2,
+            _ => 1,
+        },
+        '"' => match after_first.find('"') {
+            Some(end) if end > 0 => end + 2,
+            _ => 1,
+        },
+        c if is_name_char(c) => string.find(|c| !is_name_char(c)).unwrap_or(string.len()),
+        c if c.is_whitespace() => string
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(string.len()),
+        c => c.len_utf8(),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    is_word_char(c) || "_~!@#$%^&*-+=:|\\,./<>?".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    /// The former regex tokenizer, that the scanner must agree with.
+    fn parse_str_regex(string: &str) -> Vec<Token> {
+        let token_re = Regex::new(
+            r#"(\[\[[^\]]*\]\]|"[^"]+"|[(){};]|[\w~!@#$%^&*\-+=:|\\,.\/<>?]+|\[|\]|\s+|.)"#,
+        )
+        .unwrap();
+        let string_re = Regex::new(r#"^"[^"]+"$"#).unwrap();
+        let comment_re = Regex::new(r#"^\[\[[^\]]*\]\]$"#).unwrap();
+
+        let mut tokens = Vec::new();
+        let mut line_count = 0usize;
+        for cap in token_re.captures_iter(string) {
+            let token = match &cap[0] {
+                "(" => TokenKind::OpenParen,
+                ")" => TokenKind::CloseParen,
+                "{" => TokenKind::OpenContext,
+                "}" => TokenKind::CloseContext,
+                ";" => TokenKind::EOQ,
+                "here" => TokenKind::Here,
+                "me" => TokenKind::Me,
+                "super" => TokenKind::Super,
+                "copy" => TokenKind::Copy,
+                "at" => TokenKind::At,
+                "let" => TokenKind::Let,
+                "set" => TokenKind::Set,
+                "on" | "[" => TokenKind::On,
+                "do" | "]" => TokenKind::Do,
+                "as" => TokenKind::As,
+                "if" => TokenKind::If,
+                "return" => TokenKind::Return,
+                "repeat" => TokenKind::Repeat,
+                "break" => TokenKind::Break,
+                "continue" => TokenKind::Continue,
+                "import" => TokenKind::Import,
+                "block" => TokenKind::Block,
+                s => {
+                    line_count += s.matches('\n').count();
+                    if s.trim().is_empty() || comment_re.is_match(s) {
+                        continue;
+                    } else if string_re.is_match(s) {
+                        TokenKind::String(s[1..s.len() - 1].into())
+                    } else if let Ok(i) = s.parse::<isize>() {
+                        TokenKind::Int(i)
+                    } else if let Ok(f) = s.parse::<f64>() {
+                        TokenKind::Float(f)
+                    } else {
+                        TokenKind::Name(s.into())
+                    }
+                }
+            };
+            tokens.push(Token {
+                data: token,
+                line: line_count,
+            });
+        }
+        tokens
+    }
+
+    fn assert_same_tokens(source: &str) {
+        // Debug output is compared, because NaN floats are not equal to themselves.
+        assert_eq!(
+            format!("{:?}", parse_str(source)),
+            format!("{:?}", parse_str_regex(source)),
+            "Tokens differ for {source:?}"
+        );
+    }
+
+    #[test]
+    fn lib_and_script_files() {
+        for dir in ["lib", "scripts"] {
+            let dir = format!("{}/{dir}", env!("CARGO_MANIFEST_DIR"));
+            for entry in fs::read_dir(dir).unwrap() {
+                let source = fs::read_to_string(entry.unwrap().path()).unwrap();
+                assert_same_tokens(&source);
+            }
+        }
+    }
+
+    #[test]
+    fn edge_cases() {
+        let sources = [
+            "",
+            "[[comment]] x",
+            "[[multi\nline\ncomment]]\ny",
+            "[[unclosed comment",
+            "[[a]b]]",
+            "[ [[]] ]",
+            "[[]]",
+            "\"string\" \"\" \"unclosed",
+            "\"multi\nline\"\nz",
+            "1 -1 1.5 1e3 inf NaN 99999999999999999999",
+            "a.b:c,d+=e ~!@#$%^&*-+=:|\\,./<>? `'",
+            "(){};[]",
+            "here me super copy at let set on do as if return repeat break continue import block",
+            "héllo wörld ünïcode ✓ λx",
+            "a²b e\u{301}x a\u{200d}b a\u{200c}b x\u{203f}y ٣٤ ⅷ",
+            "\t\r\n  \n",
+        ];
+        for source in sources {
+            assert_same_tokens(source);
+        }
+    }
+
+    #[test]
+    fn shebang_line_is_skipped() {
+        let tokens = parse_str("#!/usr/bin/env proba\nx;");
+        assert_eq!(format!("{tokens:?}"), format!("{:?}", parse_str("\nx;")),);
+        assert_eq!(tokens[0].line, 1);
+    }
+
+    #[test]
+    fn random_sources() {
+        let chars = [
+            'a', 'z', '1', '.', ' ', '\n', '[', ']', '"', '(', ')', ';', ':', '-', '{', 'é', '`',
+        ];
+        let mut seed = 7u64;
+        for _ in 0..200 {
+            let mut source = String::new();
+            for _ in 0..30 {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                source.push(chars[(seed >> 33) as usize % chars.len()]);
+            }
+            assert_same_tokens(&source);
+        }
+    }
 }
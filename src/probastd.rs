@@ -1,9 +1,15 @@
-use crate::executor::{self, Interrupt, LIB_DIR};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+use crate::executor::{self, Interrupt};
 use crate::rpmt::*;
-use crate::vmstate::{Body, Pattern, State, Value};
+use crate::vmstate::{Body, FileHandle, OpenFile, Output, Pattern, State, Value};
 
 pub(crate) fn define_standard(state: &mut State) -> Result<usize, Interrupt> {
-    unsafe { executor::CURRENT_FILE_PATH = "<std>".into() }
+    state.file_path = "<std>".into();
 
     let my_objects = &mut state.objects;
     my_objects.push((0, 0, 0));
@@ -17,26 +23,111 @@ pub(crate) fn define_standard(state: &mut State) -> Result<usize, Interrupt> {
         state.define_method(
             0,
             Pattern::Kw("exit".into()),
-            Body::Rust(|state| Err(Interrupt::Exit(state.recipient().unwrap()))),
+            Body::Rust(|state| Err(Interrupt::Exit(recipient(state)?))),
+        );
+        // Objects are printed as the strings, that they answer to `toString'.
+        state.define_method(
+            0,
+            Pattern::Kw("toString".into()),
+            Body::Rust(|state| {
+                let ptr = recipient(state)?;
+                executor::new_string(state, &format!("[[Object#{ptr}]]"))
+            }),
         );
         state.define_method(
             0,
             Pattern::Kw("print".into()),
             Body::Rust(|state| {
-                let ptr = state.recipient().unwrap();
-                print!("[[Object#{ptr}]]");
+                let ptr = recipient(state)?;
+                let text = executor::to_text(state, ptr)?;
+                write_output(state, &text)?;
                 Ok(ptr)
             }),
-        ); // TODO: Redo with convertation into string
+        );
         state.define_method(
             0,
             Pattern::Kw("println".into()),
             Body::Rust(|state| {
-                let ptr = state.recipient().unwrap();
-                println!("[[Object#{ptr}]]");
+                let ptr = recipient(state)?;
+                let text = executor::to_text(state, ptr)?;
+                write_output(state, &(text + "\n"))?;
                 Ok(ptr)
             }),
-        ); // TODO: Redo with convertation into string
+        );
+        state.define_method(
+            0,
+            Pattern::Kw("inspect".into()),
+            Body::Rust(|state| {
+                let ptr = recipient(state)?;
+                write_output(state, &crate::inspect::inspect(state, ptr))?;
+                Ok(ptr)
+            }),
+        );
+    }
+
+    {
+        // Blocks run their code only when they are asked for a value,
+        // other objects are values themselves.
+        let block_ptr = exec(state, "at Object on : value do me; let Block copy Object;")?;
+        state.define_method(
+            block_ptr,
+            Pattern::Kw("value".into()),
+            Body::Rust(|state| {
+                let block_ptr = recipient(state)?;
+                executor::run_block(state, block_ptr, None)
+            }),
+        );
+        //  at Block on : value:; Object as arg do [[rust]];
+        state.define_method(
+            block_ptr,
+            Pattern::Kw("value:".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "arg".into()), |state| {
+                    let block_ptr = first_recipient(state)?;
+                    let arg_ptr = argument(state, "arg")?;
+                    executor::run_block(state, block_ptr, Some(arg_ptr))
+                })
+            }),
+        );
+    }
+
+    {
+        // Messages can be sent to another recipient, e.g. by fallback methods `doesNotUnderstand'.
+        //  at Object on : sendTo:; Object as recipient do recipient (me);
+        state.define_method(
+            0,
+            Pattern::Kw("sendTo:".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "recipient".into()), |state| {
+                    let message_ptr = first_recipient(state)?;
+                    let recipient_ptr = argument(state, "recipient")?;
+                    executor::send_message(state, recipient_ptr, message_ptr)
+                })
+            }),
+        );
+
+        // Keyword messages, that are not understood, are given as selectors.
+        let selector_ptr = exec(state, "let Selector copy Object;")?;
+        state.define_method(
+            selector_ptr,
+            Pattern::Kw("sendTo:".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "recipient".into()), |state| {
+                    let selector_ptr = first_recipient(state)?;
+                    let name = selector_name(state, selector_ptr);
+                    let recipient_ptr = argument(state, "recipient")?;
+                    executor::send_keyword(state, recipient_ptr, &name)
+                })
+            }),
+        );
+        state.define_method(
+            selector_ptr,
+            Pattern::Kw("toString".into()),
+            Body::Rust(|state| {
+                let name = selector_name(state, recipient(state)?);
+                executor::new_string(state, &format!("[:{name}]"))
+            }),
+        );
     }
 
     exec(
@@ -45,264 +136,1159 @@ pub(crate) fn define_standard(state: &mut State) -> Result<usize, Interrupt> {
         let Bool copy Object;
         let True copy Bool;
         let False copy Bool;
-        at True on : then; Object as T; : else; Object do T;
-        at False on : then; Object; : else; Object as F do F;
+        at True on : then; Object as T; : else; Object do T value;
+        at False on : then; Object; : else; Object as F do F value;
+        at True on : and; Object as X do X value;
+        at False on : and; Object do False;
+        at True on : or; Object do True;
+        at False on : or; Object as X do X value;
         ",
-    )
-    .unwrap();
+    )?;
     {
         // at True
-        let true_ptr = state
-            .get_field_value(1, "True".into())
-            .unwrap()
-            .unwrap_ptr();
+        let true_ptr = executor::global(state, "True")?;
         state.define_method(
             true_ptr,
-            Pattern::Kw("print".into()),
+            Pattern::Kw("toString".into()),
+            Body::Rust(|state| executor::new_string(state, "[[True]]")),
+        );
+
+        // at False
+        let false_ptr = executor::global(state, "False")?;
+        state.define_method(
+            false_ptr,
+            Pattern::Kw("toString".into()),
+            Body::Rust(|state| executor::new_string(state, "[[False]]")),
+        );
+    }
+
+    {
+        //  at Object on : ==; Object as other do [[rust]];
+        state.define_method(
+            0,
+            Pattern::Kw("==".into()),
+            Body::Rust(|state| take_argument(state, Pattern::PtA(0, "other".into()), is_identical)),
+        );
+    }
+
+    exec(state, "let Number copy Object;")?;
+
+    {
+        // at Int
+        let int_ptr = exec(state, "let Int copy Number;")?;
+        state.let_field(int_ptr, "value".into(), Value::Int(0));
+        // TODO: --, +, -, *, /
+
+        // on : == do { on Int as other do [[rust]]; on Object do False; here };
+        state.define_method(
+            int_ptr,
+            Pattern::Kw("==".into()),
             Body::Rust(|state| {
-                print!("[[True]]");
-                Ok(state.recipient().unwrap())
+                let int_ptr = executor::global(state, "Int")?;
+                let subcontext =
+                    take_argument(state, Pattern::PtA(int_ptr, "other".into()), |state| {
+                        // first_recipient.value == message.value
+                        let left_value = int_value(state, first_recipient(state)?)?;
+                        let right_value = int_value(state, argument(state, "other")?)?;
+                        boolean(state, left_value == right_value)
+                    })?;
+                state.define_method(
+                    subcontext,
+                    Pattern::PtA(0, "other".into()),
+                    Body::Rust(is_identical),
+                );
+                Ok(subcontext)
             }),
         );
+
         state.define_method(
-            true_ptr,
-            Pattern::Kw("println".into()),
+            int_ptr,
+            Pattern::Kw("toString".into()),
             Body::Rust(|state| {
-                println!("[[True]]");
-                Ok(state.recipient().unwrap())
+                let value = int_value(state, recipient(state)?)?;
+                executor::new_string(state, &value.to_string())
             }),
         );
 
-        // at False
-        let false_ptr = state
-            .get_field_value(1, "False".into())
-            .unwrap()
-            .unwrap_ptr();
         state.define_method(
-            false_ptr,
-            Pattern::Kw("print".into()),
+            int_ptr,
+            Pattern::Kw("++".into()),
             Body::Rust(|state| {
-                print!("[[False]]");
-                Ok(state.recipient().unwrap())
+                let recipient_ptr = recipient(state)?;
+                let value = int_value(state, recipient_ptr)?
+                    .checked_add(1)
+                    .ok_or_else(|| Interrupt::Err("Integer overflow".into()))?;
+                state.set_field(recipient_ptr, "value".into(), Value::Int(value));
+                Ok(recipient_ptr)
             }),
         );
-        let false_ptr = state
-            .get_field_value(1, "False".into())
-            .unwrap()
-            .unwrap_ptr();
+    }
+
+    exec(
+        state,
+        "let None at copy Object ( on : none? do True; );
+        at Object on : none? do False;",
+    )?;
+    {
+        let none_ptr = executor::global(state, "None")?;
         state.define_method(
-            false_ptr,
-            Pattern::Kw("println".into()),
+            none_ptr,
+            Pattern::Kw("toString".into()),
+            Body::Rust(|state| executor::new_string(state, "")),
+        );
+        state.define_method(
+            none_ptr,
+            Pattern::Kw("dbg".into()),
             Body::Rust(|state| {
-                println!("[[False]]");
-                Ok(state.recipient().unwrap())
+                write_output(state, "[[None]]\n")?;
+                recipient(state)
             }),
         );
-    } // TODO: Redo with convertation into string
+    }
 
     {
-        //  at Object on : ==; Object as other do [[rust]];
+        // Loops run in Rust, so iterations don't grow the stack.
+        let int_ptr = executor::global(state, "Int")?;
+        let block_ptr = executor::global(state, "Block")?;
+
+        //  at Int on : times; Object as body do [[rust]];
         state.define_method(
-            0,
+            int_ptr,
+            Pattern::Kw("times".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "body".into()), |state| {
+                    let count = int_value(state, first_recipient(state)?)?;
+                    let body_ptr = argument(state, "body")?;
+                    run_loop(state, body_ptr, 1, count)
+                })
+            }),
+        );
+
+        //  at Int on : to; Int as last; : do:; Object as body do [[rust]];
+        state.define_method(
+            int_ptr,
+            Pattern::Kw("to".into()),
+            Body::Rust(|state| {
+                let int_ptr = executor::global(state, "Int")?;
+                take_argument(state, Pattern::PtA(int_ptr, "last".into()), |state| {
+                    let last_ptr = argument(state, "last")?;
+                    let range = copy(state, recipient(state)?)?;
+                    state.let_field(range, "last".into(), Value::Pointer(last_ptr));
+                    state.define_method(
+                        range,
+                        Pattern::Kw("do:".into()),
+                        Body::Rust(|state| {
+                            take_argument(state, Pattern::PtA(0, "body".into()), |state| {
+                                let range = first_recipient(state)?;
+                                let first = int_value(state, range)?;
+                                let last = int_value(state, field_ptr(state, range, "last")?)?;
+                                let body_ptr = argument(state, "body")?;
+                                run_loop(state, body_ptr, first, last)
+                            })
+                        }),
+                    );
+                    Ok(range)
+                })
+            }),
+        );
+
+        //  at Block on : whileTrue; Object as body do [[rust]];
+        state.define_method(
+            block_ptr,
+            Pattern::Kw("whileTrue".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "body".into()), |state| {
+                    run_while(state, "True")
+                })
+            }),
+        );
+        //  at Block on : whileFalse; Object as body do [[rust]];
+        state.define_method(
+            block_ptr,
+            Pattern::Kw("whileFalse".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "body".into()), |state| {
+                    run_while(state, "False")
+                })
+            }),
+        );
+    }
+
+    {
+        // at String
+        let string_ptr = exec(state, "let String copy Object;")?;
+        state.strings.insert(string_ptr, String::new());
+
+        state.define_method(
+            string_ptr,
+            Pattern::Kw("toString".into()),
+            Body::Rust(|state| recipient(state)),
+        );
+        //  at String on : +; Object as other do [[rust]];
+        state.define_method(
+            string_ptr,
+            Pattern::Kw("+".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "other".into()), |state| {
+                    let left_text = text(state, first_recipient(state)?)?;
+                    let right_text = executor::to_text(state, argument(state, "other")?)?;
+                    executor::new_string(state, &(left_text + &right_text))
+                })
+            }),
+        );
+        state.define_method(
+            string_ptr,
+            Pattern::Kw("size".into()),
+            Body::Rust(|state| {
+                let size = text(state, recipient(state)?)?.chars().count();
+                executor::new_int(state, size as isize)
+            }),
+        );
+
+        // on : == do { on String as other do [[rust]]; on Object do False; here };
+        state.define_method(
+            string_ptr,
             Pattern::Kw("==".into()),
             Body::Rust(|state| {
-                let recipient_ptr = state.recipient().unwrap();
-                let subcontext = state.copy(recipient_ptr).unwrap();
-                state.contexts.push((subcontext, false));
+                let string_ptr = executor::global(state, "String")?;
+                let subcontext =
+                    take_argument(state, Pattern::PtA(string_ptr, "other".into()), |state| {
+                        let left_text = text(state, first_recipient(state)?)?;
+                        let right_text = text(state, argument(state, "other")?)?;
+                        boolean(state, left_text == right_text)
+                    })?;
                 state.define_method(
-                    state.here().unwrap(),
+                    subcontext,
                     Pattern::PtA(0, "other".into()),
-                    Body::Rust(|state| {
-                        let first_recipient_ptr = state.parent(state.recipient().unwrap()).unwrap();
-                        let other_ptr = state
-                            .get_field_value_ctx("other".into())
-                            .unwrap()
-                            .unwrap_ptr();
-                        if first_recipient_ptr == other_ptr {
-                            Ok(state
-                                .get_field_value_ctx("True".into())
-                                .unwrap()
-                                .unwrap_ptr())
-                        } else {
-                            Ok(state
-                                .get_field_value_ctx("False".into())
-                                .unwrap()
-                                .unwrap_ptr())
-                        }
-                    }),
+                    Body::Rust(is_identical),
                 );
-                Ok(state.contexts.pop().unwrap().0)
+                Ok(subcontext)
             }),
         );
     }
 
-    exec(state, "let Number copy Object;").unwrap();
+    let std_path = format!("{}/std.proba", state.lib_dir);
+    execf(state, &std_path)?;
 
     {
-        // at Int
-        let int_ptr = exec(state, "let Int copy Number;").unwrap();
-        state.contexts.push((int_ptr, false));
+        // at List
+        let list_ptr = executor::global(state, "List")?;
+        state.define_method(
+            list_ptr,
+            Pattern::Kw("size".into()),
+            Body::Rust(|state| {
+                let size = list_items(state, recipient(state)?)?.len();
+                executor::new_int(state, size as isize)
+            }),
+        );
+        //  at List on : do:; Object as body do [[rust]];
+        state.define_method(
+            list_ptr,
+            Pattern::Kw("do:".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "body".into()), |state| {
+                    let items = list_items(state, first_recipient(state)?)?;
+                    let body_ptr = argument(state, "body")?;
+                    run_each(state, body_ptr, items)
+                })
+            }),
+        );
+    }
 
-        state
-            .let_field(int_ptr, "value".into(), Value::Int(0))
-            .unwrap();
-        // TODO: ++, --, +, -, *, /
+    {
+        // at System
+        let system_ptr = exec(state, "let System copy Object;")?;
+        state.define_method(
+            system_ptr,
+            Pattern::Kw("args".into()),
+            Body::Rust(|state| {
+                let args = state.args.clone();
+                let mut items = Vec::new();
+                for arg in args {
+                    items.push(executor::new_string(state, &arg)?);
+                }
+                new_list(state, &items)
+            }),
+        );
+        //  at System on : env:; String as name do [[rust]];
+        state.define_method(
+            system_ptr,
+            Pattern::Kw("env:".into()),
+            Body::Rust(|state| {
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "name".into()), |state| {
+                    let name = text(state, argument(state, "name")?)?;
+                    match std::env::var(name) {
+                        Ok(value) => executor::new_string(state, &value),
+                        Err(_) => executor::global(state, "None"),
+                    }
+                })
+            }),
+        );
+        //  at System on : exitWith:; Int as code do [[rust]];
+        state.define_method(
+            system_ptr,
+            Pattern::Kw("exitWith:".into()),
+            Body::Rust(|state| {
+                let int_ptr = executor::global(state, "Int")?;
+                take_argument(state, Pattern::PtA(int_ptr, "code".into()), |state| {
+                    let code = int_value(state, argument(state, "code")?)?;
+                    state.exit_code = i32::try_from(code)
+                        .map_err(|_| Interrupt::Err(format!("Exit code {code} is out of range")))?;
+                    Err(Interrupt::Exit(first_recipient(state)?))
+                })
+            }),
+        );
+    }
 
-        // on : == do { on Int as other do [[rust]]; on Object do False; here };
+    {
+        // at Console
+        let console_ptr = exec(state, "let Console copy Object;")?;
+        // Answers None at the end of input.
         state.define_method(
-            int_ptr,
-            Pattern::Kw("==".into()),
+            console_ptr,
+            Pattern::Kw("readLine".into()),
+            Body::Rust(|state| {
+                let mut input = console_input(&state.output)?;
+                match read_line(&mut input).map_err(input_error)? {
+                    Some(line) => executor::new_string(state, &line),
+                    None => executor::global(state, "None"),
+                }
+            }),
+        );
+        state.define_method(
+            console_ptr,
+            Pattern::Kw("readAll".into()),
+            Body::Rust(|state| {
+                let input = console_input(&state.output)?;
+                let input = io::read_to_string(input).map_err(input_error)?;
+                executor::new_string(state, &input)
+            }),
+        );
+        //  at Console on : eachLine:; Object as body do [[rust]];
+        state.define_method(
+            console_ptr,
+            Pattern::Kw("eachLine:".into()),
             Body::Rust(|state| {
-                let recipient_ptr = state.recipient().unwrap();
-                let subcontext = state.copy(recipient_ptr).unwrap();
-                state.contexts.push((subcontext, false));
+                take_argument(state, Pattern::PtA(0, "body".into()), |state| {
+                    let body_ptr = argument(state, "body")?;
+                    let output = state.output.clone();
+                    run_each_line(state, body_ptr, || {
+                        read_line(&mut console_input(&output)?).map_err(input_error)
+                    })
+                })
+            }),
+        );
+        // Waits for input, if there is no buffered one.
+        state.define_method(
+            console_ptr,
+            Pattern::Kw("end?".into()),
+            Body::Rust(|state| {
+                let is_end = console_input(&state.output)?
+                    .fill_buf()
+                    .map_err(input_error)?
+                    .is_empty();
+                boolean(state, is_end)
+            }),
+        );
+    }
 
-                let int_ptr = state.get_field_value(1, "Int".into()).unwrap().unwrap_ptr();
-                state.define_method(
-                    state.here().unwrap(),
-                    Pattern::PtA(int_ptr, "other".into()),
-                    Body::Rust(|state| {
-                        // first_recipient.value == message.value
-                        let first_recipient_ptr = state.parent(state.recipient().unwrap()).unwrap();
-                        let left_value = state
-                            .get_field_value(first_recipient_ptr, "value".into())
-                            .unwrap()
-                            .unwrap_int();
-
-                        let other_ptr = state
-                            .get_field_value_ctx("other".into())
-                            .unwrap()
-                            .unwrap_ptr();
-                        let right_value = state
-                            .get_field_value(other_ptr, "value".into())
-                            .unwrap()
-                            .unwrap_int();
-
-                        if left_value == right_value {
-                            // TODO: Replace with get_field(1, ...)
-                            Ok(state
-                                .get_field_value(1, "True".into())
-                                .unwrap()
-                                .unwrap_ptr())
-                        } else {
-                            Ok(state
-                                .get_field_value(1, "False".into())
-                                .unwrap()
-                                .unwrap_ptr())
+    {
+        // Runtime errors can be caught; the handler block gets an Error object.
+        let error_ptr = exec(state, "let Error copy Object;")?;
+        let text_ptr = executor::new_string(state, "")?;
+        state.let_field(error_ptr, "text".into(), Value::Pointer(text_ptr));
+        state.define_method(
+            error_ptr,
+            Pattern::Kw("message".into()),
+            Body::Rust(|state| field_ptr(state, recipient(state)?, "text")),
+        );
+        state.define_method(
+            error_ptr,
+            Pattern::Kw("toString".into()),
+            Body::Rust(|state| field_ptr(state, recipient(state)?, "text")),
+        );
+        let block_ptr = executor::global(state, "Block")?;
+        //  at Block on : catch:; Object as handler do [[rust]];
+        state.define_method(
+            block_ptr,
+            Pattern::Kw("catch:".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "handler".into()), |state| {
+                    let block_ptr = first_recipient(state)?;
+                    let handler_ptr = argument(state, "handler")?;
+                    match executor::run_block(state, block_ptr, None) {
+                        Err(Interrupt::Error(_, _, message) | Interrupt::Err(message)) => {
+                            let error_ptr = copy(state, executor::global(state, "Error")?)?;
+                            let text_ptr = executor::new_string(state, &message)?;
+                            state.let_field(error_ptr, "text".into(), Value::Pointer(text_ptr));
+                            run_with_optional_arg(state, handler_ptr, Some(error_ptr))
                         }
-                    }),
-                );
+                        result => result,
+                    }
+                })
+            }),
+        );
+    }
 
-                state.define_method(
-                    state.here().unwrap(),
-                    Pattern::PtA(0, "other".into()),
-                    Body::Rust(|state| {
-                        let first_recipient_ptr = state.parent(state.recipient().unwrap()).unwrap();
-                        let other_ptr = state
-                            .get_field_value_ctx("other".into())
-                            .unwrap()
-                            .unwrap_ptr();
-
-                        if first_recipient_ptr == other_ptr {
-                            Ok(state
-                                .get_field_value_ctx("True".into())
-                                .unwrap()
-                                .unwrap_ptr())
-                        } else {
-                            Ok(state
-                                .get_field_value_ctx("False".into())
-                                .unwrap()
-                                .unwrap_ptr())
-                        }
-                    }),
-                );
+    {
+        // at File
+        let file_ptr = exec(state, "let File copy Object;")?;
 
-                Ok(state.contexts.pop().unwrap().0)
+        //  at File on : open:; String as path do [[rust]];
+        state.define_method(
+            file_ptr,
+            Pattern::Kw("open:".into()),
+            Body::Rust(|state| {
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "path".into()), |state| {
+                    open_file(state, |path| {
+                        fs::File::open(path).map(|file| OpenFile::Reader(BufReader::new(file)))
+                    })
+                })
             }),
         );
-
+        //  at File on : create:; String as path do [[rust]];
         state.define_method(
-            int_ptr,
-            Pattern::Kw("print".into()),
+            file_ptr,
+            Pattern::Kw("create:".into()),
             Body::Rust(|state| {
-                let recipient_ptr = state.recipient().unwrap();
-                let value = state
-                    .get_field_value(recipient_ptr, "value".into())
-                    .unwrap()
-                    .unwrap_int();
-                print!("{value}");
-                Ok(recipient_ptr)
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "path".into()), |state| {
+                    open_file(state, |path| fs::File::create(path).map(OpenFile::Writer))
+                })
             }),
         );
+        //  at File on : append:; String as path do [[rust]];
         state.define_method(
-            int_ptr,
-            Pattern::Kw("println".into()),
+            file_ptr,
+            Pattern::Kw("append:".into()),
             Body::Rust(|state| {
-                let recipient_ptr = state.recipient().unwrap();
-                let value = state
-                    .get_field_value(recipient_ptr, "value".into())
-                    .unwrap()
-                    .unwrap_int();
-                println!("{value}");
-                Ok(recipient_ptr)
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "path".into()), |state| {
+                    open_file(state, |path| {
+                        let mut options = fs::OpenOptions::new();
+                        options
+                            .append(true)
+                            .create(true)
+                            .open(path)
+                            .map(OpenFile::Writer)
+                    })
+                })
             }),
         );
 
+        // Answers None at the end of the file.
         state.define_method(
-            int_ptr,
-            Pattern::Kw("++".into()),
+            file_ptr,
+            Pattern::Kw("readLine".into()),
+            Body::Rust(|state| {
+                let (path, handle) = file_handle(state, recipient(state)?)?;
+                let line = with_reader(&path, &handle, |reader| read_line(reader))?;
+                match line {
+                    Some(line) => executor::new_string(state, &line),
+                    None => executor::global(state, "None"),
+                }
+            }),
+        );
+        state.define_method(
+            file_ptr,
+            Pattern::Kw("readAll".into()),
             Body::Rust(|state| {
-                let recipient_ptr = state.recipient().unwrap();
-                let value = state
-                    .get_field_value(recipient_ptr, "value".into())
-                    .unwrap()
-                    .unwrap_int();
-                state.set_field(recipient_ptr, "value".into(), Value::Int(value + 1));
+                let (path, handle) = file_handle(state, recipient(state)?)?;
+                let contents = with_reader(&path, &handle, |reader| {
+                    let mut contents = String::new();
+                    reader.read_to_string(&mut contents).map(|_| contents)
+                })?;
+                executor::new_string(state, &contents)
+            }),
+        );
+        //  at File on : eachLine:; Object as body do [[rust]];
+        state.define_method(
+            file_ptr,
+            Pattern::Kw("eachLine:".into()),
+            Body::Rust(|state| {
+                take_argument(state, Pattern::PtA(0, "body".into()), |state| {
+                    let (path, handle) = file_handle(state, first_recipient(state)?)?;
+                    let body_ptr = argument(state, "body")?;
+                    run_each_line(state, body_ptr, || {
+                        with_reader(&path, &handle, |reader| read_line(reader))
+                    })
+                })
+            }),
+        );
+        //  at File on : write:; String as text do [[rust]];
+        state.define_method(
+            file_ptr,
+            Pattern::Kw("write:".into()),
+            Body::Rust(|state| {
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "text".into()), |state| {
+                    let file_ptr = first_recipient(state)?;
+                    let text = text(state, argument(state, "text")?)?;
+                    write_file(state, file_ptr, &text)?;
+                    Ok(file_ptr)
+                })
+            }),
+        );
+        //  at File on : writeLine:; String as text do [[rust]];
+        state.define_method(
+            file_ptr,
+            Pattern::Kw("writeLine:".into()),
+            Body::Rust(|state| {
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "text".into()), |state| {
+                    let file_ptr = first_recipient(state)?;
+                    let text = text(state, argument(state, "text")?)?;
+                    write_file(state, file_ptr, &(text + "\n"))?;
+                    Ok(file_ptr)
+                })
+            }),
+        );
+        state.define_method(
+            file_ptr,
+            Pattern::Kw("close".into()),
+            Body::Rust(|state| {
+                let recipient_ptr = recipient(state)?;
+                let (_, handle) = file_handle(state, recipient_ptr)?;
+                handle.borrow_mut().take();
                 Ok(recipient_ptr)
             }),
         );
 
-        state.contexts.pop().unwrap();
-    }
-
-    exec(
-        state,
-        "let None at copy Object ( on : none? do True; );
-        at Object on : none? do False;",
-    )
-    .unwrap();
-    {
-        let none_ptr = state
-            .get_field_value(1, "None".into())
-            .unwrap()
-            .unwrap_ptr();
+        //  at File on : exists:; String as path do [[rust]];
         state.define_method(
-            none_ptr,
-            Pattern::Kw("print".into()),
+            file_ptr,
+            Pattern::Kw("exists:".into()),
             Body::Rust(|state| {
-                print!("");
-                Ok(state.recipient().unwrap())
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "path".into()), |state| {
+                    let path = text(state, argument(state, "path")?)?;
+                    boolean(state, std::path::Path::new(&path).exists())
+                })
             }),
         );
+        //  at File on : delete:; String as path do [[rust]];
         state.define_method(
-            none_ptr,
-            Pattern::Kw("println".into()),
+            file_ptr,
+            Pattern::Kw("delete:".into()),
             Body::Rust(|state| {
-                println!("");
-                Ok(state.recipient().unwrap())
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "path".into()), |state| {
+                    let path = text(state, argument(state, "path")?)?;
+                    fs::remove_file(&path).map_err(|err| file_error("delete", &path, err))?;
+                    first_recipient(state)
+                })
             }),
         );
+        //  at File on : rename:; String as from; : to:; String as to do [[rust]];
         state.define_method(
-            none_ptr,
-            Pattern::Kw("dbg".into()),
+            file_ptr,
+            Pattern::Kw("rename:".into()),
+            Body::Rust(|state| {
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "from".into()), |state| {
+                    let from_ptr = argument(state, "from")?;
+                    let renaming = copy(state, recipient(state)?)?;
+                    state.let_field(renaming, "from".into(), Value::Pointer(from_ptr));
+                    state.define_method(
+                        renaming,
+                        Pattern::Kw("to:".into()),
+                        Body::Rust(|state| {
+                            let string_ptr = executor::global(state, "String")?;
+                            take_argument(state, Pattern::PtA(string_ptr, "to".into()), |state| {
+                                let renaming = first_recipient(state)?;
+                                let from = text(state, field_ptr(state, renaming, "from")?)?;
+                                let to = text(state, argument(state, "to")?)?;
+                                fs::rename(&from, &to)
+                                    .map_err(|err| file_error("rename", &from, err))?;
+                                executor::global(state, "File")
+                            })
+                        }),
+                    );
+                    Ok(renaming)
+                })
+            }),
+        );
+        //  at File on : list:; String as path do [[rust]];
+        state.define_method(
+            file_ptr,
+            Pattern::Kw("list:".into()),
             Body::Rust(|state| {
-                println!("[[None]]");
-                Ok(state.recipient().unwrap())
+                let string_ptr = executor::global(state, "String")?;
+                take_argument(state, Pattern::PtA(string_ptr, "path".into()), |state| {
+                    let path = text(state, argument(state, "path")?)?;
+                    let mut names = Vec::new();
+                    for entry in
+                        fs::read_dir(&path).map_err(|err| file_error("list", &path, err))?
+                    {
+                        let entry = entry.map_err(|err| file_error("list", &path, err))?;
+                        names.push(entry.file_name().to_string_lossy().into_owned());
+                    }
+                    names.sort();
+                    let mut items = Vec::new();
+                    for name in names {
+                        items.push(executor::new_string(state, &name)?);
+                    }
+                    new_list(state, &items)
+                })
             }),
         );
     }
 
-    execf(state, &(LIB_DIR.to_string() + "/std.proba"))?;
+    Ok(0)
+}
+
+/// Answer a copy of the recipient, that takes the argument by the native method,
+/// like `on : keyword; Pattern as name do ...' does.
+fn take_argument(
+    state: &mut State,
+    pattern: Pattern,
+    body: fn(&mut State) -> Result<usize, Interrupt>,
+) -> Result<usize, Interrupt> {
+    let subcontext = copy(state, recipient(state)?)?;
+    state.define_method(subcontext, pattern, Body::Rust(body));
+    Ok(subcontext)
+}
+
+fn recipient(state: &State) -> Result<usize, Interrupt> {
+    state
+        .recipient()
+        .ok_or_else(|| Interrupt::Err("Native method is called outside of a method".into()))
+}
+
+/// Recipient of the keyword-method, that answered the copy taking the argument.
+fn first_recipient(state: &State) -> Result<usize, Interrupt> {
+    let ptr = recipient(state)?;
+    state
+        .parent(ptr)
+        .ok_or_else(|| Interrupt::Err(format!("Object #{ptr} does not exist")))
+}
+
+/// Get the argument of the running native method.
+fn argument(state: &State, name: &str) -> Result<usize, Interrupt> {
+    match state.get_field_value_ctx(name.into()) {
+        Some(Value::Pointer(ptr)) => Ok(ptr),
+        _ => Err(Interrupt::Err(format!("There is no argument `{name}'"))),
+    }
+}
+
+fn field_ptr(state: &State, ptr: usize, name: &str) -> Result<usize, Interrupt> {
+    match state.get_field_value(ptr, name.into()) {
+        Some(Value::Pointer(ptr)) => Ok(ptr),
+        _ => Err(Interrupt::Err(format!(
+            "{} has no field `{name}'",
+            state.describe(ptr)
+        ))),
+    }
+}
+
+fn int_value(state: &State, ptr: usize) -> Result<isize, Interrupt> {
+    match state.get_field_value(ptr, "value".into()) {
+        Some(Value::Int(value)) => Ok(value),
+        _ => Err(Interrupt::Err(format!(
+            "{} has no integer value",
+            state.describe(ptr)
+        ))),
+    }
+}
 
-    return Ok(0);
+/// Print the text to the output of the interpreter.
+fn write_output(state: &State, text: &str) -> Result<(), Interrupt> {
+    state
+        .output
+        .write(text)
+        .map_err(|err| Interrupt::Err(format!("Failed to write output: {err}")))
+}
+
+fn text(state: &State, ptr: usize) -> Result<String, Interrupt> {
+    match state.text_of(ptr) {
+        Some(text) => Ok(text.clone()),
+        None => Err(Interrupt::Err(format!(
+            "{} has no text",
+            state.describe(ptr)
+        ))),
+    }
+}
+
+/// Elements of the list, that is made of `Node's of `lib/list.proba' ending with `End'.
+fn list_items(state: &State, list_ptr: usize) -> Result<Vec<usize>, Interrupt> {
+    let end_ptr = field_ptr(state, list_ptr, "End")?;
+    let mut node_ptr = field_ptr(state, list_ptr, "head")?;
+    let mut items = Vec::new();
+    while state.relation(node_ptr, end_ptr).is_none() {
+        if items.len() > state.objects.len() {
+            Err(Interrupt::Err(format!(
+                "{} is cyclic",
+                state.describe(list_ptr)
+            )))?
+        }
+        items.push(field_ptr(state, node_ptr, "data")?);
+        node_ptr = field_ptr(state, node_ptr, "next")?;
+    }
+    Ok(items)
+}
+
+fn new_list(state: &mut State, items: &[usize]) -> Result<usize, Interrupt> {
+    let list_ptr = copy(state, executor::global(state, "List")?)?;
+    let node_ptr = field_ptr(state, list_ptr, "Node")?;
+    let mut next_ptr = field_ptr(state, list_ptr, "End")?;
+    for item in items.iter().rev() {
+        let new_node_ptr = copy(state, node_ptr)?;
+        state.let_field(new_node_ptr, "data".into(), Value::Pointer(*item));
+        state.let_field(new_node_ptr, "next".into(), Value::Pointer(next_ptr));
+        next_ptr = new_node_ptr;
+    }
+    state.let_field(list_ptr, "head".into(), Value::Pointer(next_ptr));
+    Ok(list_ptr)
+}
+
+/// Read the line without its line break; None at the end.
+fn read_line(reader: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let length = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(length);
+    Ok(Some(line))
+}
+
+/// Input of the console; the printed text is flushed before it is read.
+fn console_input(output: &Output) -> Result<io::StdinLock<'static>, Interrupt> {
+    output
+        .flush()
+        .map_err(|err| Interrupt::Err(format!("Failed to write output: {err}")))?;
+    Ok(io::stdin().lock())
+}
+
+fn input_error(err: io::Error) -> Interrupt {
+    Interrupt::Err(format!("Failed to read input: {err}"))
+}
+
+fn file_error(action: &str, path: &str, err: io::Error) -> Interrupt {
+    Interrupt::Err(format!("Failed to {action} file `{path}': {err}"))
+}
+
+/// Open the file at the argument `path' and answer a new File object for it.
+fn open_file(
+    state: &mut State,
+    open: fn(&str) -> io::Result<OpenFile>,
+) -> Result<usize, Interrupt> {
+    let path = text(state, argument(state, "path")?)?;
+    let file = open(&path).map_err(|err| file_error("open", &path, err))?;
+    let file_ptr = copy(state, executor::global(state, "File")?)?;
+    state
+        .files
+        .push((file_ptr, path, Rc::new(RefCell::new(Some(file)))));
+    Ok(file_ptr)
+}
+
+fn file_handle(state: &State, ptr: usize) -> Result<(String, FileHandle), Interrupt> {
+    match state.files.iter().find(|file| file.0 == ptr) {
+        Some((_, path, handle)) => Ok((path.clone(), handle.clone())),
+        None => Err(Interrupt::Err(format!(
+            "{} is not an opened file",
+            state.describe(ptr)
+        ))),
+    }
+}
+
+fn with_reader<T>(
+    path: &str,
+    handle: &FileHandle,
+    read: impl FnOnce(&mut dyn BufRead) -> io::Result<T>,
+) -> Result<T, Interrupt> {
+    match handle.borrow_mut().as_mut() {
+        Some(OpenFile::Reader(reader)) => read(reader).map_err(|err| file_error("read", path, err)),
+        Some(OpenFile::Writer(_)) => Err(Interrupt::Err(format!(
+            "File `{path}' is not opened for reading"
+        ))),
+        None => Err(Interrupt::Err(format!("File `{path}' is closed"))),
+    }
+}
+
+fn write_file(state: &State, file_ptr: usize, text: &str) -> Result<(), Interrupt> {
+    let (path, handle) = file_handle(state, file_ptr)?;
+    let result = match handle.borrow_mut().as_mut() {
+        Some(OpenFile::Writer(file)) => file.write_all(text.as_bytes()),
+        Some(OpenFile::Reader(_)) => Err(Interrupt::Err(format!(
+            "File `{path}' is not opened for writing"
+        )))?,
+        None => Err(Interrupt::Err(format!("File `{path}' is closed")))?,
+    };
+    result.map_err(|err| file_error("write", &path, err))
+}
+
+/// Run the body for each line, until `next_line' answers None.
+fn run_each_line(
+    state: &mut State,
+    body_ptr: usize,
+    mut next_line: impl FnMut() -> Result<Option<String>, Interrupt>,
+) -> Result<usize, Interrupt> {
+    let mut answer = executor::global(state, "None")?;
+    while let Some(line) = next_line()? {
+        let line_ptr = executor::new_string(state, &line)?;
+        match run_iteration(state, body_ptr, Some(line_ptr))? {
+            ControlFlow::Continue(ptr) => answer = ptr,
+            ControlFlow::Break(ptr) => return Ok(ptr),
+        }
+    }
+    Ok(answer)
+}
+
+fn copy(state: &mut State, ptr: usize) -> Result<usize, Interrupt> {
+    state
+        .copy(ptr)
+        .ok_or_else(|| Interrupt::Err(format!("Object #{ptr} does not exist")))
+}
+
+fn boolean(state: &State, value: bool) -> Result<usize, Interrupt> {
+    executor::global(state, if value { "True" } else { "False" })
+}
+
+/// Compare the first recipient with the argument `other' by identity.
+fn is_identical(state: &mut State) -> Result<usize, Interrupt> {
+    let first_recipient_ptr = first_recipient(state)?;
+    let other_ptr = argument(state, "other")?;
+    boolean(state, first_recipient_ptr == other_ptr)
+}
+
+/// Get keyword of the selector or of its prototype.
+fn selector_name(state: &State, ptr: usize) -> String {
+    let mut ptr = ptr;
+    loop {
+        if let Some((_, name)) = state.selectors.iter().find(|selector| selector.0 == ptr) {
+            return name.clone();
+        }
+        match state.parent(ptr) {
+            Some(parent) if parent != ptr => ptr = parent,
+            _ => return String::new(),
+        }
+    }
+}
+
+/// Run the block; the argument is passed only, if the block has a parameter.
+fn run_with_optional_arg(
+    state: &mut State,
+    block_ptr: usize,
+    arg: Option<usize>,
+) -> Result<usize, Interrupt> {
+    let arg = match state.blocks.iter().find(|block| block.0 == block_ptr) {
+        Some((_, Some(_), ..)) => arg,
+        _ => None,
+    };
+    executor::run_block(state, block_ptr, arg)
+}
+
+/// Run the body block once; a block with parameter gets the index.
+/// `Break` holds the answer of the whole loop.
+/// Only `break' and `continue', that are written in the same method as the body, leave the loop.
+fn run_iteration(
+    state: &mut State,
+    body_ptr: usize,
+    index: Option<usize>,
+) -> Result<ControlFlow<usize, usize>, Interrupt> {
+    let scope = state
+        .blocks
+        .iter()
+        .find(|block| block.0 == body_ptr)
+        .and_then(|block| executor::loop_scope(&block.3));
+    match run_with_optional_arg(state, body_ptr, index) {
+        Ok(answer) => Ok(ControlFlow::Continue(answer)),
+        Err(Interrupt::Continue(answer, target)) if Some(target) == scope => {
+            Ok(ControlFlow::Continue(answer))
+        }
+        Err(Interrupt::Break(answer, target)) if Some(target) == scope => {
+            Ok(ControlFlow::Break(answer))
+        }
+        Err(int) => Err(int),
+    }
+}
+
+/// Run the body for every index from first to last; answer the last answer of the body or None.
+fn run_loop(
+    state: &mut State,
+    body_ptr: usize,
+    first: isize,
+    last: isize,
+) -> Result<usize, Interrupt> {
+    let mut answer = executor::global(state, "None")?;
+    for i in first..=last {
+        let index = executor::new_int(state, i)?;
+        match run_iteration(state, body_ptr, Some(index))? {
+            ControlFlow::Continue(ptr) => answer = ptr,
+            ControlFlow::Break(ptr) => return Ok(ptr),
+        }
+    }
+    Ok(answer)
+}
+
+/// Run the body for each item; the item is passed, if the block has a parameter.
+fn run_each(state: &mut State, body_ptr: usize, items: Vec<usize>) -> Result<usize, Interrupt> {
+    let mut answer = executor::global(state, "None")?;
+    for item in items {
+        match run_iteration(state, body_ptr, Some(item))? {
+            ControlFlow::Continue(ptr) => answer = ptr,
+            ControlFlow::Break(ptr) => return Ok(ptr),
+        }
+    }
+    Ok(answer)
+}
+
+/// Run the body, while the recipient block answers the expected boolean.
+fn run_while(state: &mut State, expected: &str) -> Result<usize, Interrupt> {
+    let condition_ptr = first_recipient(state)?;
+    let body_ptr = argument(state, "body")?;
+    let expected_ptr = executor::global(state, expected)?;
+    let mut answer = executor::global(state, "None")?;
+    loop {
+        let condition = match run_iteration(state, condition_ptr, None)? {
+            ControlFlow::Continue(ptr) => ptr,
+            ControlFlow::Break(ptr) => return Ok(ptr),
+        };
+        if condition != expected_ptr {
+            return Ok(answer);
+        }
+        match run_iteration(state, body_ptr, None)? {
+            ControlFlow::Continue(ptr) => answer = ptr,
+            ControlFlow::Break(ptr) => return Ok(ptr),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// State with the standard objects, that prints to the buffer.
+    pub(crate) fn state_with_buffer() -> (State, Rc<RefCell<String>>) {
+        let mut state = State::new();
+        let buffer = Rc::new(RefCell::new(String::new()));
+        state.output = Output::Buffer(buffer.clone());
+        define_standard(&mut state).unwrap();
+        (state, buffer)
+    }
+
+    #[test]
+    fn output_is_captured() {
+        let (mut state, buffer) = state_with_buffer();
+
+        exec(
+            &mut state,
+            "5 println; \"text\" print; True println; None println;",
+        )
+        .unwrap();
+        assert_eq!(*buffer.borrow(), "5\ntext[[True]]\n\n");
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let callback_lines = lines.clone();
+        state.output = Output::Callback(Rc::new(move |text| {
+            callback_lines.borrow_mut().push(text.to_string())
+        }));
+        exec(&mut state, "1 print; 2 println;").unwrap();
+        assert_eq!(*lines.borrow(), ["1", "2\n"]);
+    }
+
+    #[test]
+    fn same_guarded_method_is_redefined() {
+        let mut state = State::new();
+        define_standard(&mut state).unwrap();
+
+        exec(
+            &mut state,
+            "let F copy Object; at F on Int as n if (n == 0) do 100;",
+        )
+        .unwrap();
+        let answer = exec(&mut state, "at F on Int as n if (n == 0) do 200; F 0;").unwrap();
+        assert_eq!(int_value(&state, answer).unwrap(), 200);
+        let answer = exec(&mut state, "at F on Int as n if (n == 1) do 300; F 0;").unwrap();
+        assert_eq!(int_value(&state, answer).unwrap(), 200);
+    }
+
+    #[test]
+    fn methods_access_arguments_of_all_parts() {
+        let mut state = State::new();
+        define_standard(&mut state).unwrap();
+
+        exec(
+            &mut state,
+            "let Pair copy Object; at Pair on : first; Object as a; : second; Object as b do a;
+            let Point copy Object; at Point ( let x 1; on : show do x; );
+            let Other copy Object; at Other on : call; Object as p do (p show; p);",
+        )
+        .unwrap();
+        let answer = exec(&mut state, "Pair first 1 second 2;").unwrap();
+        assert_eq!(int_value(&state, answer).unwrap(), 1);
+        let answer = exec(&mut state, "Point show;").unwrap();
+        assert_eq!(int_value(&state, answer).unwrap(), 1);
+        // Fields of the recipient are checked from the caller's context.
+        assert!(exec(&mut state, "Other call Point;").is_err());
+    }
+
+    #[test]
+    fn tail_calls_run_past_the_call_depth_limit() {
+        let mut state = State::new();
+        define_standard(&mut state).unwrap();
+        state.max_call_depth = 100;
+
+        exec(
+            &mut state,
+            "let Counter copy Object;
+            at Counter on Int as n if (n == 5000) do n;
+            at Counter on Int as n do Counter (n ++);",
+        )
+        .unwrap();
+        let objects_len = state.objects.len();
+        let answer = exec(&mut state, "Counter 0;").unwrap();
+        assert_eq!(int_value(&state, answer).unwrap(), 5000);
+        // Replaced methods leave no objects behind.
+        assert!(state.objects.len() < objects_len + 10);
+    }
+
+    #[test]
+    fn blocks_are_run_by_value() {
+        let (mut state, buffer) = state_with_buffer();
+
+        exec(
+            &mut state,
+            "let n 0; let inc block (n ++); inc value; inc value; n print;
+            let adder block as a (block as b (a ++)); ((adder value: 3) value: 4) print;
+            (block as x (x)) value none? print; 5 value print;",
+        )
+        .unwrap();
+        assert_eq!(*buffer.borrow(), "24[[True]]5");
+        let result = exec(&mut state, "inc value: 1;");
+        assert!(matches!(
+            result,
+            Err(Interrupt::Error(_, _, message)) if message == "The block does not take an argument"
+        ));
+    }
+
+    #[test]
+    fn break_and_continue_leave_loops_of_their_method() {
+        let (mut state, buffer) = state_with_buffer();
+
+        exec(
+            &mut state,
+            "let M copy Object; at M on : stop do break 7;
+            at M on Object as body do 3 times block (body value);
+            (5 times block as i ((i == 2) then block (break i) else block (continue))) print;
+            (5 times block as i (M block (break i))) print;",
+        )
+        .unwrap();
+        assert_eq!(*buffer.borrow(), "21");
+        let result = exec(&mut state, "5 times block (M stop);");
+        assert!(matches!(
+            result,
+            Err(Interrupt::Error(_, _, message)) if message == "`break' or `continue' is used outside of a loop"
+        ));
+    }
+
+    #[test]
+    fn objects_are_printed_by_to_string() {
+        let (mut state, buffer) = state_with_buffer();
+
+        exec(
+            &mut state,
+            "let Point copy Object; at Point ( let x 1; on : toString do \"Point \" + x; );
+            Point println; (\"x = \" + 5 + \" \" + Point) print;",
+        )
+        .unwrap();
+        assert_eq!(*buffer.borrow(), "Point 1\nx = 5 Point 1");
+        assert!(exec(&mut state, "at Point on : toString do 5; Point println;").is_err());
+    }
+
+    #[test]
+    fn inspect_shows_cycles_once() {
+        let (mut state, buffer) = state_with_buffer();
+
+        exec(
+            &mut state,
+            "let Point copy Object; at Point ( let x 1; let inner copy Object; on : y do 2; );
+            at Point at inner let owner Point; Point inspect;",
+        )
+        .unwrap();
+        let report = buffer.borrow();
+        assert!(report.starts_with("Point (Object)\n"));
+        assert!(report.contains("  parents: Object #0\n  context: #1 (global context)\n"));
+        assert!(report.contains("    x = "));
+        assert!(report.contains("        owner = Point (Object) (shown above)\n"));
+        assert!(report.contains("    keyword y (in "));
+    }
+
+    #[test]
+    fn files_are_written_and_read() {
+        let (mut state, buffer) = state_with_buffer();
+        let dir = std::env::temp_dir().join(format!("proba-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir_path = dir.to_string_lossy();
+
+        exec(
+            &mut state,
+            &format!(
+                "let f (File create: \"{dir_path}/a.txt\"); f writeLine: \"one\"; f write: \"two\"; f close;
+                (File append: \"{dir_path}/a.txt\") writeLine: \"!\";
+                let r (File open: \"{dir_path}/a.txt\");
+                r readLine println; r readAll print; r readLine none? println; r close;
+                (File open: \"{dir_path}/a.txt\") eachLine: block as line ((\"> \" + line) println);"
+            ),
+        )
+        .unwrap();
+        assert_eq!(*buffer.borrow(), "one\ntwo!\n[[True]]\n> one\n> two!\n");
+        assert!(exec(&mut state, "r readLine;").is_err());
+
+        buffer.borrow_mut().clear();
+        exec(
+            &mut state,
+            &format!(
+                "(File exists: \"{dir_path}/a.txt\") println;
+                File rename: \"{dir_path}/a.txt\" to: \"{dir_path}/b.txt\";
+                (File list: \"{dir_path}\") do: block as name (name println);
+                File delete: \"{dir_path}/b.txt\";
+                (File exists: \"{dir_path}/b.txt\") println;"
+            ),
+        )
+        .unwrap();
+        assert_eq!(*buffer.borrow(), "[[True]]\nb.txt\n[[False]]\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_failures_are_caught() {
+        let (mut state, buffer) = state_with_buffer();
+
+        exec(
+            &mut state,
+            "block (File open: \"/nonexistent/file\") catch: block as error (error message println);
+            (block (File delete: \"/nonexistent/file\") catch: block (5)) println;",
+        )
+        .unwrap();
+        assert_eq!(
+            *buffer.borrow(),
+            "Failed to open file `/nonexistent/file': No such file or directory (os error 2)\n5\n"
+        );
+        assert!(exec(&mut state, "File open: \"/nonexistent/file\";").is_err());
+    }
+
+    #[test]
+    fn unreachable_files_are_closed() {
+        let mut state = State::new();
+        define_standard(&mut state).unwrap();
+        let path = std::env::temp_dir().join(format!("proba-closed-{}", std::process::id()));
+        fs::write(&path, "line\n").unwrap();
+        let path_text = path.to_string_lossy();
+
+        exec(
+            &mut state,
+            &format!(
+                "let kept (File open: \"{path_text}\");
+                10 times block ((File open: \"{path_text}\") readLine);"
+            ),
+        )
+        .unwrap();
+        // Only the file in the field stays open.
+        assert_eq!(state.files.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
 }
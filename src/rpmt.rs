@@ -1,6 +1,6 @@
 // Rusty Proba-Module Tools
 use crate::{
-    executor::{execute, Interrupt, CURRENT_FILE_PATH},
+    executor::{execute, Interrupt},
     lexer::lex,
     parser::{parse_file, parse_str},
     vmstate::State,
@@ -8,18 +8,21 @@ use crate::{
 
 pub fn exec(state: &mut State, code: &str) -> Result<usize, Interrupt> {
     let tokens = parse_str(code);
-    let node_tree = lex(tokens);
+    let node_tree = lex(tokens, &state.file_path);
     execute(state, node_tree)
 }
 
 pub fn execf(state: &mut State, file_path: &str) -> Result<usize, Interrupt> {
-    let tokens = parse_file(file_path.into()).unwrap();
-    let node_tree = lex(tokens);
-    let prev_file = unsafe { CURRENT_FILE_PATH.clone() };
-
-    unsafe { CURRENT_FILE_PATH = file_path.into() };
+    let tokens = match parse_file(file_path.into()) {
+        Ok(tokens) => tokens,
+        Err(err) => Err(Interrupt::Err(format!(
+            "Failed to open file `{file_path}': {err}"
+        )))?,
+    };
+    let prev_file = std::mem::replace(&mut state.file_path, file_path.into());
+    let node_tree = lex(tokens, file_path);
     let result = execute(state, node_tree);
-    unsafe { CURRENT_FILE_PATH = prev_file };
+    state.file_path = prev_file;
     result
 }
 
@@ -30,6 +33,6 @@ pub fn at(
 ) -> Result<usize, Interrupt> {
     state.contexts.push((at_ptr, false));
     let result = func(state);
-    state.contexts.pop().unwrap();
+    state.contexts.pop();
     result
 }
@@ -1,5 +1,12 @@
-use crate::executor::{Interrupt, CURRENT_FILE_PATH};
-use crate::lexer::Node;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, Write};
+use std::rc::Rc;
+
+use crate::compiler::Code;
+use crate::executor::Interrupt;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Value {
@@ -29,13 +36,24 @@ impl Value {
     }
 }
 
-#[derive(Debug, Clone, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub enum Pattern {
     Kw(String),
     Eq(usize),
     EqA(usize, String),
     Pt(usize),
     PtA(usize, String),
+    /// Pattern matches only if the guard answers True.
+    Guarded(Box<Pattern>, Rc<Code>),
+}
+
+impl Pattern {
+    pub fn unguarded(&self) -> &Pattern {
+        match self {
+            Self::Guarded(pattern, _) => pattern,
+            pattern => pattern,
+        }
+    }
 }
 
 impl PartialEq for Pattern {
@@ -44,17 +62,135 @@ impl PartialEq for Pattern {
             (Self::Kw(l0), Self::Kw(r0)) => l0 == r0,
             (Self::Eq(l0) | Self::EqA(l0, _), Self::Eq(r0) | Self::EqA(r0, _)) => l0 == r0,
             (Self::Pt(l0) | Self::PtA(l0, _), Self::Pt(r0) | Self::PtA(r0, _)) => l0 == r0,
+            (Self::Guarded(l0, l1), Self::Guarded(r0, r1)) => l0 == r0 && l1 == r1,
             _ => false,
         }
     }
 }
 
+/// (owner_ptr, pattern, body, file)
+pub type Method = (usize, Pattern, Body, String);
+/// (ptr, parameter, code, captured contexts, file)
+pub type Block = (usize, Option<String>, Rc<Code>, Vec<(usize, bool)>, String);
+/// Opened file of a File object; None, after the file is closed.
+pub type FileHandle = Rc<RefCell<Option<OpenFile>>>;
+
+#[derive(Debug)]
+pub enum OpenFile {
+    Reader(BufReader<fs::File>),
+    Writer(fs::File),
+}
+
+/// Sink of the text, that is printed by `print' and `println' methods.
+#[derive(Clone, Default)]
+pub enum Output {
+    #[default]
+    Stdout,
+    Buffer(Rc<RefCell<String>>),
+    Writer(Rc<RefCell<dyn Write>>),
+    Callback(Rc<dyn Fn(&str)>),
+}
+
+impl Output {
+    pub fn write(&self, text: &str) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().write_all(text.as_bytes()),
+            Output::Buffer(buffer) => {
+                buffer.borrow_mut().push_str(text);
+                Ok(())
+            }
+            Output::Writer(writer) => writer.borrow_mut().write_all(text.as_bytes()),
+            Output::Callback(callback) => {
+                callback(text);
+                Ok(())
+            }
+        }
+    }
+    /// Printed text must not stay in the buffer before a prompt or an input.
+    pub fn flush(&self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Writer(writer) => writer.borrow_mut().flush(),
+            Output::Buffer(_) | Output::Callback(_) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Stdout => write!(f, "Stdout"),
+            Output::Buffer(buffer) => write!(f, "Buffer({} bytes)", buffer.borrow().len()),
+            Output::Writer(_) => write!(f, "Writer"),
+            Output::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Body {
-    Do(Node),
+    Do(Rc<Code>),
     Rust(fn(&mut State) -> Result<usize, Interrupt>),
 }
 
+/// Stamps of method changes and the keyword-method lookup cache.
+///
+/// Every change of methods of an owner gives it a new stamp,
+/// so cached lookups are valid while stamps of the walked owners are the same.
+#[derive(Clone, Default)]
+pub struct MethodCache {
+    stamp: usize,
+    layout: usize, // Stamp of the last removal from `State::methods`
+    owners: HashMap<usize, usize>,
+    pattern_objects: HashSet<usize>,
+    keywords: RefCell<HashMap<(usize, String), CachedKeyword>>,
+}
+
+#[derive(Clone)]
+struct CachedKeyword {
+    layout: usize,
+    owners: Vec<(usize, usize)>,
+    index: Option<usize>,
+}
+
+impl MethodCache {
+    fn next_stamp(&mut self) -> usize {
+        self.stamp += 1;
+        self.stamp
+    }
+}
+
+impl fmt::Debug for MethodCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MethodCache")
+            .field("stamp", &self.stamp)
+            .field("keywords", &self.keywords.borrow().len())
+            .finish()
+    }
+}
+
+/// Monomorphic cache of a message-sending site.
+#[derive(Debug, Default)]
+pub struct SendCache(RefCell<Option<CachedSend>>);
+
+/// Caches don't make compiled codes different.
+impl PartialEq for SendCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct CachedSend {
+    recipient: usize,
+    message: usize,
+    layout: usize,
+    owners: Vec<(usize, usize)>,
+    method: Method,
+}
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
 #[derive(Debug, Clone)]
 pub struct State {
     pub op_count: usize,
@@ -63,6 +199,21 @@ pub struct State {
     pub objects: Vec<(usize, usize, usize)>, // (ptr, parent_ptr, cotnext_ptr)
     pub fields: Vec<(usize, String, Value)>, // (owner_ptr, name, ptr|int|float)
     pub methods: Vec<(usize, Pattern, Body, String)>, // (owner_ptr, pattern, body, file)
+    pub blocks: Vec<Block>,
+    pub selectors: Vec<(usize, String)>, // (ptr, keyword) of keyword messages as objects
+    pub strings: HashMap<usize, String>, // Texts of String objects by their pointers
+    pub files: Vec<(usize, String, FileHandle)>, // (ptr, path, handle) of File objects
+    pub method_cache: MethodCache,
+    pub method_owners: Vec<(usize, usize)>, // (method context, owner of the running method)
+    pub native_contexts: Vec<usize>,        // Contexts of the running native methods
+    pub part_owners: HashMap<usize, usize>, // Owners of finished methods by their kept contexts
+    pub call_depth: usize,                  // Number of running methods
+    pub max_call_depth: usize,              // Deeper calls are runtime errors
+    pub exit_code: i32,                     // Status of the process, when the program exits
+    pub output: Output,                     // Where objects are printed
+    pub file_path: String,                  // File of the running code
+    pub lib_dir: String,                    // Directory of the standard library and modules
+    pub args: Vec<String>,                  // Arguments of the program
 }
 
 impl State {
@@ -73,6 +224,21 @@ impl State {
             objects: Vec::new(),
             fields: Vec::new(),
             methods: Vec::new(),
+            blocks: Vec::new(),
+            selectors: Vec::new(),
+            strings: HashMap::new(),
+            files: Vec::new(),
+            method_cache: MethodCache::default(),
+            method_owners: Vec::new(),
+            native_contexts: Vec::new(),
+            part_owners: HashMap::new(),
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            exit_code: 0,
+            output: Output::Stdout,
+            file_path: String::new(),
+            lib_dir: crate::executor::LIB_DIR.into(),
+            args: Vec::new(),
         }
     }
 
@@ -82,11 +248,69 @@ impl State {
         self.objects = other.objects.clone();
         self.fields = other.fields.clone();
         self.methods = other.methods.clone();
+        self.blocks = other.blocks.clone();
+        self.selectors = other.selectors.clone();
+        self.strings = other.strings.clone();
+        self.files = other.files.clone();
+        self.method_owners = other.method_owners.clone();
+        self.native_contexts = other.native_contexts.clone();
+        self.part_owners = other.part_owners.clone();
+        self.call_depth = other.call_depth;
+        self.max_call_depth = other.max_call_depth;
+        self.exit_code = other.exit_code;
+        self.output = other.output.clone();
+        self.file_path = other.file_path.clone();
+        self.lib_dir = other.lib_dir.clone();
+        self.args = other.args.clone();
+        // Stamps must not repeat, because pointers of the other state may be reused.
+        let stamp = self.method_cache.stamp.max(other.method_cache.stamp);
+        self.method_cache = other.method_cache.clone();
+        self.method_cache.keywords.borrow_mut().clear();
+        self.method_cache.stamp = stamp;
+        self.method_cache.layout = self.method_cache.next_stamp();
     }
 
     pub fn here(&self) -> Option<usize> {
         Some(self.contexts.last()?.0)
     }
+    /// The context, that entered the current one. Methods called by native methods
+    /// are entered from the context of the code, that called the native method.
+    pub fn prev_context(&self) -> Option<usize> {
+        self.contexts[..self.contexts.len().checked_sub(1)?]
+            .iter()
+            .rev()
+            .map(|(ptr, _)| *ptr)
+            .find(|ptr| !self.native_contexts.contains(ptr))
+    }
+    /// Return None, when was called at not method.
+    pub fn method_context(&self) -> Option<usize> {
+        self.contexts
+            .iter()
+            .rev()
+            .find(|(_, is_for_method)| *is_for_method)
+            .map(|(ptr, _)| *ptr)
+    }
+    /// Return None, when was called at not method.
+    pub fn running_method_owner(&self) -> Option<usize> {
+        let context = self.method_context()?;
+        let (_, owner) = self
+            .method_owners
+            .iter()
+            .rev()
+            .find(|(ptr, _)| *ptr == context)?;
+        Some(*owner)
+    }
+    /// Later parts of a multi-part method are methods of sub-contexts of the previous parts;
+    /// the method is defined by the owner of its first part.
+    pub fn definition_owner(&self, mut owner: usize) -> usize {
+        while let Some(&first) = self
+            .parent(owner)
+            .and_then(|ptr| self.part_owners.get(&ptr))
+        {
+            owner = first;
+        }
+        owner
+    }
     /// Return None, when was called at not method.
     pub fn recipient(&self) -> Option<usize> {
         for (ptr, is_for_method) in self.contexts.iter().rev() {
@@ -96,14 +320,28 @@ impl State {
         }
         None
     }
+    /// Objects are always sorted by pointer.
+    fn object(&self, ptr: usize) -> Option<&(usize, usize, usize)> {
+        let index = self.objects.binary_search_by_key(&ptr, |obj| obj.0).ok()?;
+        Some(&self.objects[index])
+    }
     pub fn copy(&mut self, ptr: usize) -> Option<usize> {
-        self.objects.iter().find(|obj| obj.0 == ptr)?;
+        self.object(ptr)?;
+        let context = self.here()?;
         let new_ptr = self.op_count;
         self.op_count += 1;
-        self.objects
-            .push((new_ptr, ptr, self.contexts.last().unwrap().0));
+        self.objects.push((new_ptr, ptr, context));
         return Some(new_ptr);
     }
+    /// Move objects since the pointer `first' from the context to another one.
+    pub fn move_objects(&mut self, first: usize, from: usize, to: usize) {
+        let start = self.objects.partition_point(|obj| obj.0 < first);
+        for obj in &mut self.objects[start..] {
+            if obj.2 == from {
+                obj.2 = to;
+            }
+        }
+    }
     pub fn relation(&self, ptr: usize, parent_ptr: usize) -> Option<usize> {
         if ptr == parent_ptr {
             return Some(0);
@@ -124,20 +362,20 @@ impl State {
     }
 
     pub fn parent(&self, ptr: usize) -> Option<usize> {
-        Some(self.objects.iter().find(|obj| obj.0 == ptr)?.1)
+        Some(self.object(ptr)?.1)
     }
     pub fn context_of(&self, ptr: usize) -> Option<usize> {
-        Some(self.objects.iter().find(|obj| obj.0 == ptr)?.2)
+        Some(self.object(ptr)?.2)
     }
 
     /// Return Some if success, else None.
     pub fn let_field(&mut self, ptr: usize, name: String, value: Value) -> Option<()> {
-        self.objects.iter().find(|obj| obj.0 == ptr)?;
+        self.object(ptr)?;
         // get_mut(&(ptr, name.clone()))
         match self
             .fields
             .iter_mut()
-            .find(|field| (field.0, field.1.clone()) == (ptr, name.clone()))
+            .find(|field| field.0 == ptr && field.1 == name)
         {
             Some(field) => field.2 = value,
             None => self.fields.push((ptr, name.clone(), value)),
@@ -148,7 +386,7 @@ impl State {
         let field = self
             .fields
             .iter_mut()
-            .find(|field| (field.0, field.1.clone()) == (ptr, name.clone()))?;
+            .find(|field| field.0 == ptr && field.1 == name)?;
         (*field).2 = value;
         Some(())
     }
@@ -157,7 +395,7 @@ impl State {
         match self
             .fields
             .iter()
-            .find(|field| (field.0, field.1.clone()) == (ptr, name.clone()))
+            .find(|field| field.0 == ptr && field.1 == name)
         {
             Some(field) => {
                 let result = Some((field.0, field.2));
@@ -169,7 +407,7 @@ impl State {
                     // println!("GET FIELD OF {ptr} NAMED {name} => None");
                     None?
                 } else {
-                    let parent_ptr = self.objects.iter().find(|obj| obj.0 == ptr)?.1;
+                    let parent_ptr = self.parent(ptr)?;
                     let result = self.get_field(parent_ptr, name.clone());
                     // println!("GET FIELD OF {ptr} NAMED {name} => {result:?}");
                     result
@@ -187,15 +425,15 @@ impl State {
         // that is a copy of the current context-object's creation context.
         // Exception: the global context.
         // println!("GET FIELD VALUE OF CTX NAMED {name}...");
-        let prev_context = if self.contexts.len() <= 1 {
-            None
-        } else {
-            Some(self.contexts[self.contexts.len() - 2].0)
-        };
+        let prev_context = self.prev_context();
 
         for (ptr, is_for_method) in self.contexts.iter().rev() {
             // println!("Next context: {ptr}");
             match self.get_field(*ptr, name.clone()) {
+                // Arguments of the running method are always accessible.
+                Some((owner, value)) if *is_for_method && self.is_argument_owner(*ptr, owner) => {
+                    return Some(value)
+                }
                 Some((ptr, value)) if self.have_access_premission(prev_context, ptr) => {
                     // println!("GET FIELD VALUE OF CTX NAMED {} => {:?}", name, Some(value));
                     return Some(value);
@@ -211,21 +449,137 @@ impl State {
         result
     }
 
+    /// Describe the object for error messages: its name, names of its prototypes and its value.
+    pub fn describe(&self, ptr: usize) -> String {
+        let mut description = self.name_of(ptr).unwrap_or(format!("#{ptr}"));
+        if let Some(Value::Int(value)) = self.get_field_value(ptr, "value".into()) {
+            description += &format!(" = {value}");
+        } else if let Some(text) = self.text_of(ptr) {
+            description += &format!(" = {text:?}");
+        }
+        let mut prototypes = Vec::new();
+        let mut parent_ptr = ptr;
+        while parent_ptr != 0 {
+            parent_ptr = match self.parent(parent_ptr) {
+                Some(parent_ptr) => parent_ptr,
+                None => break,
+            };
+            prototypes.push(self.name_of(parent_ptr).unwrap_or(format!("#{parent_ptr}")));
+        }
+        if !prototypes.is_empty() {
+            description += &format!(" ({})", prototypes.join(" < "));
+        }
+        description
+    }
+    /// Text of the String object; copies of a string have its text.
+    pub fn text_of(&self, ptr: usize) -> Option<&String> {
+        let mut ptr = ptr;
+        loop {
+            if let Some(text) = self.strings.get(&ptr) {
+                return Some(text);
+            }
+            match self.parent(ptr) {
+                Some(parent_ptr) if ptr != 0 => ptr = parent_ptr,
+                _ => return None,
+            }
+        }
+    }
+    /// Name of a global field or of a prototype-like (capitalized) field, that is bound to the object.
+    pub fn name_of(&self, ptr: usize) -> Option<String> {
+        if let Some((_, keyword)) = self.selectors.iter().find(|selector| selector.0 == ptr) {
+            return Some(format!("[:{keyword}]"));
+        }
+        let mut names = self
+            .fields
+            .iter()
+            .filter(|field| matches!(field.2, Value::Pointer(p) if p == ptr));
+        let global_name = names.clone().find(|field| field.0 == 1);
+        global_name
+            .or_else(|| names.find(|field| field.1.starts_with(char::is_uppercase)))
+            .map(|field| field.1.clone())
+    }
+    /// Keywords of methods of the object and of its prototypes.
+    pub fn keywords_of(&self, ptr: usize) -> Vec<String> {
+        let mut keywords = Vec::new();
+        let mut ptr = ptr;
+        loop {
+            for method in self.methods.iter().filter(|method| method.0 == ptr) {
+                if let Pattern::Kw(keyword) = &method.1 {
+                    keywords.push(keyword.clone());
+                }
+            }
+            match self.parent(ptr) {
+                Some(parent_ptr) if ptr != 0 => ptr = parent_ptr,
+                _ => return keywords,
+            }
+        }
+    }
+    /// Field names and keywords, that are accessible in the current context.
+    pub fn names_ctx(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut contexts = Vec::new();
+        for &(ptr, is_for_method) in self.contexts.iter().rev() {
+            contexts.push(ptr);
+            if is_for_method {
+                break;
+            }
+        }
+        contexts.extend(self.contexts.first().map(|context| context.0));
+        for ptr in contexts {
+            names.extend(self.keywords_of(ptr));
+            let mut ptr = ptr;
+            loop {
+                names.extend(
+                    self.fields
+                        .iter()
+                        .filter(|field| field.0 == ptr)
+                        .map(|field| field.1.clone()),
+                );
+                match self.parent(ptr) {
+                    Some(parent_ptr) if ptr != 0 => ptr = parent_ptr,
+                    _ => break,
+                }
+            }
+        }
+        names
+    }
+
+    /// Arguments of the method are fields of its context; arguments of the previous parts
+    /// of a multi-part method are fields of the sub-contexts, that answered the recipient.
+    /// Sub-contexts are copies of the context, where they are created.
+    fn is_argument_owner(&self, method_context: usize, owner: usize) -> bool {
+        let mut ptr = method_context;
+        loop {
+            match self.object(ptr) {
+                Some(&(_, parent, context)) if parent == context && parent != ptr => {
+                    if ptr == owner {
+                        return true;
+                    }
+                    ptr = parent;
+                }
+                _ => return false,
+            }
+        }
+    }
+
     fn have_access_premission(&self, prev_context: Option<usize>, ptr: usize) -> bool {
-        if prev_context.is_none() {
+        let prev_context = match prev_context {
             // println!("HAVE ACCESS FROM {prev_context:?} AT {ptr} => true (GLOBAL)");
-            true
-        } else if self.relation(prev_context.unwrap(), ptr).is_some() {
+            None => return true,
+            Some(prev_context) => prev_context,
+        };
+        if self.relation(prev_context, ptr).is_some() {
             // println!("HAVE ACCESS FROM {prev_context:?} AT {ptr} => true (1 -> 2)");
             true
         } else {
-            let context_of_target_ptr = self
-                .context_of(ptr)
-                .expect(&format!("Failed to get context of object #{ptr}."));
-            let result = self
-                .relation(prev_context.unwrap(), context_of_target_ptr)
-                .is_some();
-            // println!("HAVE ACCESS FROM {prev_context:?} AT {ptr} (_, _, {context_of_target_ptr}) => {result}");
+            // Objects without a context are not accessible from other contexts.
+            let result = match self.context_of(ptr) {
+                Some(context_of_target_ptr) => {
+                    self.relation(prev_context, context_of_target_ptr).is_some()
+                }
+                None => false,
+            };
+            // println!("HAVE ACCESS FROM {prev_context:?} AT {ptr} => {result}");
             result
         }
     }
@@ -237,39 +591,169 @@ impl State {
         let some_method_pos = self
             .methods
             .iter()
-            .position(|method| (method.0, method.1.clone()) == (ptr, pattern.clone()));
+            .position(|method| method.0 == ptr && self.is_same_pattern(&method.1, &pattern));
         let redefined = if let Some(index) = some_method_pos {
             self.methods.remove(index);
+            self.method_cache.layout = self.method_cache.next_stamp();
             true
         } else {
             false
         };
+        let stamp = self.method_cache.next_stamp();
+        self.method_cache.owners.insert(ptr, stamp);
+        match pattern.unguarded() {
+            Pattern::Eq(p) | Pattern::EqA(p, _) | Pattern::Pt(p) | Pattern::PtA(p, _) => {
+                self.method_cache.pattern_objects.insert(*p);
+            }
+            Pattern::Kw(_) | Pattern::Guarded(..) => (),
+        }
         self.methods
-            .push((ptr, pattern, body, unsafe { CURRENT_FILE_PATH.clone() }));
+            .push((ptr, pattern, body, self.file_path.clone()));
         redefined
     }
+    /// Literals of equalness-patterns are new objects on every definition,
+    /// so patterns with equal literals are same.
+    fn is_same_pattern(&self, old: &Pattern, new: &Pattern) -> bool {
+        match (old, new) {
+            (Pattern::Eq(l0) | Pattern::EqA(l0, _), Pattern::Eq(r0) | Pattern::EqA(r0, _)) => {
+                l0 == r0 || self.are_equal_literals(*l0, *r0)
+            }
+            (Pattern::Guarded(l0, l1), Pattern::Guarded(r0, r1)) => {
+                l1 == r1 && self.is_same_pattern(l0, r0)
+            }
+            (old, new) => old == new,
+        }
+    }
+    /// Ints and strings of the same prototype with the same value.
+    fn are_equal_literals(&self, ptr: usize, other: usize) -> bool {
+        let int_value = |ptr| {
+            self.fields
+                .iter()
+                .find_map(|(owner, name, value)| match value {
+                    Value::Int(value) if *owner == ptr && name == "value" => Some(*value),
+                    _ => None,
+                })
+        };
+        if self.parent(ptr) != self.parent(other) {
+            return false;
+        }
+        match (self.strings.get(&ptr), self.strings.get(&other)) {
+            (Some(text), Some(other_text)) => text == other_text,
+            (None, None) => {
+                matches!((int_value(ptr), int_value(other)), (Some(l0), Some(r0)) if l0 == r0)
+            }
+            _ => false,
+        }
+    }
     /// Use when message is a name (word (keyword)).
     pub fn get_method(
         &self,
         ptr: usize,
         keyword: String,
     ) -> Option<&(usize, Pattern, Body, String)> {
-        match self
-            .methods
-            .iter()
-            .find(|method| (method.0, method.1.clone()) == (ptr, Pattern::Kw(keyword.clone())))
-        {
-            Some(method) => Some(method),
+        let owner = self.method_owner(ptr)?;
+        let key = (owner, keyword);
+        let cached = self.method_cache.keywords.borrow().get(&key).cloned();
+        let index = match cached {
+            Some(cached)
+                if cached.layout == self.method_cache.layout
+                    && self.are_stamps_fresh(&cached.owners) =>
+            {
+                cached.index
+            }
+            _ => {
+                let index = self.find_keyword_method(owner, &key.1);
+                let cached = CachedKeyword {
+                    layout: self.method_cache.layout,
+                    owners: self.owner_stamps(owner),
+                    index,
+                };
+                self.method_cache.keywords.borrow_mut().insert(key, cached);
+                index
+            }
+        };
+        Some(&self.methods[index?])
+    }
+    fn find_keyword_method(&self, ptr: usize, keyword: &str) -> Option<usize> {
+        match self.methods.iter().position(
+            |method| matches!(&method.1, Pattern::Kw(kw) if method.0 == ptr && kw == keyword),
+        ) {
+            Some(index) => Some(index),
             None => {
                 if ptr == 0 {
                     None?
                 } else {
-                    let parent_ptr = self.objects.iter().find(|obj| obj.0 == ptr)?.1;
-                    self.get_method(parent_ptr, keyword)
+                    self.find_keyword_method(self.parent(ptr)?, keyword)
                 }
             }
         }
     }
+
+    /// Return the nearest object in the parent chain, that has own methods.
+    pub(crate) fn method_owner(&self, ptr: usize) -> Option<usize> {
+        let mut ptr = ptr;
+        while ptr != 0 && !self.method_cache.owners.contains_key(&ptr) {
+            ptr = self.parent(ptr)?;
+        }
+        Some(ptr)
+    }
+    /// Return the nearest object in the parent chain, that is used in a method pattern.
+    /// Objects below it match the same prototype-patterns.
+    pub(crate) fn pattern_key(&self, ptr: usize) -> Option<usize> {
+        let mut ptr = ptr;
+        while ptr != 0 && !self.method_cache.pattern_objects.contains(&ptr) {
+            ptr = self.parent(ptr)?;
+        }
+        Some(ptr)
+    }
+    /// Method stamps of the object and all its parents.
+    fn owner_stamps(&self, ptr: usize) -> Vec<(usize, usize)> {
+        let mut stamps = Vec::new();
+        let mut ptr = Some(ptr);
+        while let Some(p) = ptr {
+            stamps.push((p, *self.method_cache.owners.get(&p).unwrap_or(&0)));
+            ptr = if p == 0 { None } else { self.parent(p) };
+        }
+        stamps
+    }
+    fn are_stamps_fresh(&self, stamps: &[(usize, usize)]) -> bool {
+        stamps
+            .iter()
+            .all(|(ptr, stamp)| self.method_cache.owners.get(ptr).unwrap_or(&0) == stamp)
+    }
+    /// Get method matched at the sending site before, if methods of the recipient are same.
+    pub(crate) fn cached_send(
+        &self,
+        cache: &SendCache,
+        recipient: usize,
+        message: usize,
+    ) -> Option<Method> {
+        let cached = cache.0.borrow();
+        let cached = cached.as_ref()?;
+        if (cached.recipient, cached.message) == (recipient, message)
+            && cached.layout == self.method_cache.layout
+            && self.are_stamps_fresh(&cached.owners)
+        {
+            Some(cached.method.clone())
+        } else {
+            None
+        }
+    }
+    pub(crate) fn cache_send(
+        &self,
+        cache: &SendCache,
+        recipient: usize,
+        message: usize,
+        method: Method,
+    ) {
+        *cache.0.borrow_mut() = Some(CachedSend {
+            recipient,
+            message,
+            layout: self.method_cache.layout,
+            owners: self.owner_stamps(recipient),
+            method,
+        });
+    }
     /// Use when message is a name (word (keyword)).
     pub fn get_method_ctx(&self, keyword: String) -> Option<&(usize, Pattern, Body, String)> {
         for &(ptr, is_for_method) in self.contexts.iter().rev() {
@@ -284,53 +768,91 @@ impl State {
         self.get_method(self.contexts.first()?.0, keyword)
     }
 
-    fn count_links(&self, ptr: usize) -> usize {
-        let mut count = 0;
-        // As parent and context owner
-        for (_, parent, context) in &self.objects {
-            if *parent == ptr {
-                count += 1;
-            }
-            if *context == ptr {
-                count += 1;
+    /// Delete objects created since the `first' pointer, that are not reachable
+    /// from older objects, from the contexts, from patterns of methods and from the white list.
+    /// Objects, that are only held by running code, are older than the code, so they are kept.
+    pub(crate) fn clear_garbage_since(&mut self, first: usize, white_list: &[usize]) {
+        let start = self.objects.partition_point(|obj| obj.0 < first);
+        if start == self.objects.len() {
+            return;
+        }
+        // Links of new objects to others; links of old objects are roots.
+        let mut links: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut pending = white_list.to_vec();
+        let mut link = |owner: usize, ptr: usize| match owner < first {
+            true => pending.push(ptr),
+            false => links.entry(owner).or_default().push(ptr),
+        };
+        for (owner, _, value) in &self.fields {
+            if let Value::Pointer(ptr) = value {
+                link(*owner, *ptr);
             }
         }
-        // As field value
-        for field in &self.fields {
-            if let Value::Pointer(p) = field.2 {
-                if p == ptr {
-                    count += 1;
-                }
+        for (owner, pattern, ..) in &self.methods {
+            match pattern.unguarded() {
+                Pattern::Eq(ptr)
+                | Pattern::EqA(ptr, _)
+                | Pattern::Pt(ptr)
+                | Pattern::PtA(ptr, _) => link(*owner, *ptr),
+                Pattern::Kw(_) | Pattern::Guarded(..) => (),
             }
         }
-        // As context
-        for (p, _) in &self.contexts {
-            if *p == ptr {
-                count += 1;
+        for (ptr, _, _, contexts, _) in &self.blocks {
+            for (context, _) in contexts {
+                link(*ptr, *context);
             }
         }
+        pending.extend(self.contexts.iter().map(|(ptr, _)| *ptr));
+        pending.extend(
+            self.method_owners
+                .iter()
+                .flat_map(|(ptr, owner)| [*ptr, *owner]),
+        );
 
-        count
-    }
-    pub(crate) fn clear_garbage(&mut self, white_list: Vec<usize>) {
-        let mut run = true;
-        while run {
-            run = false;
-            for (_, ptr, _) in self.objects.clone() {
-                if white_list.contains(&ptr) {
-                    continue;
-                }
-                let n = self.count_links(ptr);
-                if n == 0 {
-                    self.delete_object(ptr);
-                    run = true;
-                }
+        let mut reachable = HashSet::new();
+        while let Some(ptr) = pending.pop() {
+            if ptr < first || !reachable.insert(ptr) {
+                continue;
+            }
+            if let Some(&(_, parent, context)) = self.object(ptr) {
+                pending.extend([parent, context]);
             }
+            pending.extend(links.get(&ptr).into_iter().flatten());
         }
+        let garbage = self.objects[start..]
+            .iter()
+            .map(|obj| obj.0)
+            .filter(|ptr| !reachable.contains(ptr))
+            .collect::<HashSet<usize>>();
+        self.delete_objects(&garbage);
     }
-    fn delete_object(&mut self, ptr: usize) {
-        self.methods.retain(|method| method.0 != ptr);
-        self.fields.retain(|field| field.0 != ptr);
-        self.objects.retain(|obj| obj.0 != ptr);
+    fn delete_objects(&mut self, ptrs: &HashSet<usize>) {
+        if ptrs.is_empty() {
+            return;
+        }
+        let mut had_methods = false;
+        for ptr in ptrs {
+            had_methods |= self.method_cache.owners.remove(ptr).is_some();
+        }
+        if had_methods {
+            self.methods.retain(|method| !ptrs.contains(&method.0));
+            self.method_cache.layout = self.method_cache.next_stamp();
+        }
+        self.fields.retain(|field| !ptrs.contains(&field.0));
+        self.blocks.retain(|block| !ptrs.contains(&block.0));
+        self.selectors
+            .retain(|selector| !ptrs.contains(&selector.0));
+        for ptr in ptrs {
+            self.strings.remove(ptr);
+            self.part_owners.remove(ptr);
+        }
+        // Handles may be shared with snapshots of the state, so the files are closed explicitly.
+        self.files.retain(|(ptr, _, handle)| {
+            if ptrs.contains(ptr) {
+                handle.borrow_mut().take();
+            }
+            !ptrs.contains(ptr)
+        });
+        self.objects.retain(|obj| !ptrs.contains(&obj.0));
     }
 }
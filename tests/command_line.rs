@@ -0,0 +1,290 @@
+// Modes and options of the interpreter, that are given on the command line.
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::{env, fs};
+
+/// Run the interpreter with the library of the crate; the input is piped to its stdin.
+fn proba(args: &[&str], input: &str) -> Output {
+    proba_with_env(args, &[], input)
+}
+
+fn proba_with_env(args: &[&str], vars: &[(&str, &str)], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_proba-lang"))
+        .args(["-lib-dir", concat!(env!("CARGO_MANIFEST_DIR"), "/lib")])
+        .args(args)
+        .envs(vars.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+const MULTI_PATTERN_DEFINITION: &str = "on : a; Int as x if (x == 0) do x;";
+
+#[test]
+fn syntax_tree_of_multi_pattern_definition_is_dumped() {
+    let output = proba(&["-dump-ast", "-e", MULTI_PATTERN_DEFINITION], "");
+    assert_eq!(
+        stdout(&output),
+        "Queue  [line 1]
+  OnDo  [line 1]
+    Pattern Keyword  [line 1]
+      Name a  [line 1]
+    Body
+      QuickContext  [line 1]
+        OnDo  [line 1]
+          As x  [line 1]
+            Pattern Prototype  [line 1]
+              Name Int  [line 1]
+          Guard
+            Queue  [line 1]
+              Message  [line 1]
+                Message  [line 1]
+                  Name x  [line 1]
+                  Name ==  [line 1]
+                Int 0  [line 1]
+          Body
+            Name x  [line 1]
+        Here  [line 1]
+"
+    );
+}
+
+#[test]
+fn syntax_tree_of_multi_pattern_definition_is_dumped_as_json() {
+    let output = proba(
+        &["-dump-ast", "-dump-json", "-e", MULTI_PATTERN_DEFINITION],
+        "",
+    );
+    let expected = concat!(
+        r#"{"kind":"Queue","line":1,"children":[{"kind":"OnDo","line":1,"children":["#,
+        r#"{"kind":"Pattern","value":"Keyword","line":1,"children":["#,
+        r#"{"kind":"Name","value":"a","line":1}]},"#,
+        r#"{"kind":"Body","children":[{"kind":"QuickContext","line":1,"children":["#,
+        r#"{"kind":"OnDo","line":1,"children":[{"kind":"As","value":"x","line":1,"children":["#,
+        r#"{"kind":"Pattern","value":"Prototype","line":1,"children":["#,
+        r#"{"kind":"Name","value":"Int","line":1}]}]},"#,
+        r#"{"kind":"Guard","children":[{"kind":"Queue","line":1,"children":["#,
+        r#"{"kind":"Message","line":1,"children":[{"kind":"Message","line":1,"children":["#,
+        r#"{"kind":"Name","value":"x","line":1},{"kind":"Name","value":"==","line":1}]},"#,
+        r#"{"kind":"Int","value":0,"line":1}]}]}]},"#,
+        r#"{"kind":"Body","children":[{"kind":"Name","value":"x","line":1}]}]},"#,
+        r#"{"kind":"Here","line":1}]}]}]}]}"#,
+        "\n",
+    );
+    assert_eq!(stdout(&output), expected);
+}
+
+/// Directory with the program files, that is removed after the test.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let dir = env::temp_dir().join(format!("proba-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file_name, contents) in files {
+            fs::write(dir.join(file_name), contents).unwrap();
+        }
+        Self(dir)
+    }
+
+    fn path(&self, file_name: &str) -> String {
+        self.0.join(file_name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn check_accepts_program_with_imports() {
+    let dir = TempDir::new(
+        "check-ok",
+        &[
+            ("main.proba", "import point here; 1 println;"),
+            ("point.proba", "let x 1;"),
+        ],
+    );
+    let output = proba(&["-check", &dir.path("main.proba")], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Checked 2 files, no errors found\n");
+}
+
+#[test]
+fn check_fails_on_syntax_error() {
+    let output = proba(&["-check", "-e", "1 println; let"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).starts_with("Syntax error on line 1 in `<code>'"));
+
+    let dir = TempDir::new(
+        "check-syntax",
+        &[
+            ("main.proba", "import broken here;"),
+            ("broken.proba", "on : x do"),
+        ],
+    );
+    let output = proba(&["-check", &dir.path("main.proba")], "");
+    assert_eq!(output.status.code(), Some(1));
+    let expected = format!("Syntax error on line 1 in `{}'", dir.path("broken.proba"));
+    assert!(stdout(&output).starts_with(&expected));
+}
+
+#[test]
+fn check_fails_on_missing_import() {
+    let output = proba(&["-check", "-e", "\n import nonexistent here;"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "Import error on line 2 in `<code>': There is no module with name `nonexistent'\n"
+    );
+}
+
+#[test]
+fn code_is_given_by_option() {
+    let output = proba(&["-e", "\"from option\" println; 7 println;"], "");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "from option\n7\n");
+}
+
+#[test]
+fn uncaught_errors_fail_the_program() {
+    let output = proba(&["-e", "1 println; x;"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "1\n\nRuntime error on line 1 in `<code>':\n Undefined keyword-method or field name: x\n"
+    );
+
+    let output = proba(&["-"], "1 println;\n(");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).starts_with("Syntax error on line 2 in `<stdin>'"));
+
+    let output = proba(&["/nonexistent/program.proba"], "");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = proba(&["-e", "block (x) catch: block (1);"], "");
+    assert!(output.status.success());
+}
+
+#[test]
+fn program_is_read_from_stdin() {
+    let output = proba(&["-"], "\"from stdin\" println;\nx;\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "from stdin\n\nRuntime error on line 2 in `<stdin>':\n Undefined keyword-method or field name: x\n"
+    );
+}
+
+#[test]
+fn shebang_line_is_skipped() {
+    let source = "#!/usr/bin/env proba-lang\n\"script\" println;\nx;\n";
+    let dir = TempDir::new("shebang", &[("script.proba", source)]);
+    let script_path = dir.path("script.proba");
+    let output = proba(&[&script_path], "");
+    let expected = format!(
+        "script\n\nRuntime error on line 3 in `{script_path}':\n Undefined keyword-method or field name: x\n"
+    );
+    assert_eq!(stdout(&output), expected);
+
+    let output = proba(&["-"], source);
+    assert!(stdout(&output).starts_with("script\n\nRuntime error on line 3 in `<stdin>'"));
+}
+
+#[test]
+fn program_gets_arguments_after_code() {
+    let code = "System args do: block as arg (arg println); System args first println;";
+    let output = proba(&["-e", code, "first", "second"], "");
+    assert_eq!(stdout(&output), "first\nsecond\nfirst\n");
+
+    let dir = TempDir::new("args", &[("args.proba", code)]);
+    let output = proba(&[&dir.path("args.proba"), "-not-an-option"], "");
+    assert_eq!(stdout(&output), "-not-an-option\n-not-an-option\n");
+
+    let output = proba(&["-e", "System args empty? println;"], "");
+    assert_eq!(stdout(&output), "[[True]]\n");
+}
+
+#[test]
+fn environment_variables_are_read() {
+    let code =
+        "(System env: \"PROBA_TEST_VAR\") println; (System env: \"PROBA_NO_VAR\") none? println;";
+    let output = proba_with_env(&["-e", code], &[("PROBA_TEST_VAR", "value")], "");
+    assert_eq!(stdout(&output), "value\n[[True]]\n");
+}
+
+#[test]
+fn exit_status_is_set_by_program() {
+    let output = proba(&["-e", "1 println; System exitWith: 3; 2 println;"], "");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stdout(&output), "1\n");
+
+    let output = proba(&["-e", "System exitWith: 0;"], "");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = proba(&["-e", "System exitWith: 4294967296;"], "");
+    assert!(stdout(&output).contains("Exit code 4294967296 is out of range"));
+}
+
+#[test]
+fn console_reads_piped_input() {
+    let code = "Console end? println; Console readLine println; Console readLine println;
+        Console readAll print; Console end? println; Console readLine none? println;";
+    let output = proba(&["-e", code], "first\r\nsecond\nrest 1\nrest 2\n");
+    assert_eq!(
+        stdout(&output),
+        "[[False]]\nfirst\nsecond\nrest 1\nrest 2\n[[True]]\n[[True]]\n"
+    );
+
+    let code = "Console eachLine: block as line ((\"> \" + line) println);";
+    let output = proba(&["-e", code], "a\nb");
+    assert_eq!(stdout(&output), "> a\n> b\n");
+
+    let output = proba(&["-e", "Console end? println;"], "");
+    assert_eq!(stdout(&output), "[[True]]\n");
+}
+
+#[test]
+fn pit_commands_are_undone_and_saved() {
+    let dir = TempDir::new("pit-save", &[]);
+    let session_path = dir.path("session.proba");
+    let input = format!(
+        "let x 0;\nset x (x ++);\nx;\n:undo\n:undo\nx println;\nundefined;\n:save {session_path}\n"
+    );
+    let output = proba(&["-pit"], &input);
+    let text = stdout(&output);
+    assert!(text.contains("pit> Undone: x;\npit> Undone: set x (x ++);\npit> 0\n"));
+    // Failed commands are not saved.
+    assert_eq!(
+        fs::read_to_string(&session_path).unwrap(),
+        "let x 0;\nx println;\n"
+    );
+}
+
+#[test]
+fn pit_keeps_last_hundred_steps_to_undo() {
+    let mut input = "let x 0;\n".to_string();
+    input += &"set x (x ++);\n".repeat(100);
+    input += &":undo\n".repeat(101);
+    input += "x println;\n";
+    let output = proba(&["-pit"], &input);
+    let text = stdout(&output);
+    assert_eq!(text.matches("Undone: set x (x ++);").count(), 100);
+    assert!(text.contains("pit> Nothing to undo.\npit> 0\n"));
+}
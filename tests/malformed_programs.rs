@@ -0,0 +1,190 @@
+// Malformed programs must be reported as errors, not abort the interpreter.
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+const TOKENS: &[&str] = &[
+    "(",
+    ")",
+    "{",
+    "}",
+    ";",
+    ";",
+    ";",
+    "here",
+    "me",
+    "copy",
+    "at",
+    "let",
+    "set",
+    "on",
+    "do",
+    "as",
+    "if",
+    "return",
+    "repeat",
+    "break",
+    "continue",
+    "import",
+    "super",
+    "x",
+    "y",
+    "Object",
+    "Int",
+    "True",
+    "False",
+    "None",
+    "Bool",
+    "Block",
+    "Selector",
+    "Number",
+    "0",
+    "1",
+    "-1",
+    "7",
+    "1.5",
+    "\"s\"",
+    "[[c]]",
+    "[",
+    "]",
+    ":",
+    "==",
+    "println",
+    "print",
+    "value",
+    "value:",
+    "then",
+    "else",
+    "and",
+    "or",
+    "times",
+    "to",
+    "do:",
+    "whileTrue",
+    "whileFalse",
+    "sendTo:",
+    "doesNotUnderstand",
+    "++",
+    "none?",
+];
+
+const PROGRAMS: &[&str] = &[
+    "let",
+    "let x",
+    "set",
+    "on : x",
+    "on : x do",
+    "at 5 (value);",
+    "\"text\" println;",
+    "1.5 println;",
+    "repeat;",
+    "break;",
+    "return 1;",
+    "super println;",
+    "import nonexistent here;",
+    "let x 9223372036854775807; x ++;",
+    "at Object on : ==; Object do Object; on == 5 do 1; 5;",
+    "at Int on : ==; Object do 1; on == 5 do 1; 5;",
+    "Object doesNotUnderstand;",
+    "at Object on : doesNotUnderstand do 1; x;",
+    "Selector sendTo: 1;",
+    "Block value;",
+    "3 to Object do: { 1 };",
+    "True then 1 else 2;",
+];
+
+/// Deterministic generator of token soups.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as usize % bound
+    }
+}
+
+/// Run the program; answer false, if the interpreter panicked.
+fn runs_without_panic(program: &str, file_path: &PathBuf) -> bool {
+    fs::write(file_path, program).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_proba-lang"))
+        .args(["-lib-dir", concat!(env!("CARGO_MANIFEST_DIR"), "/lib")])
+        .args(["-max-depth", "200"])
+        .arg(file_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        match child.try_wait().unwrap() {
+            Some(status) => break status,
+            // Endless loops are valid programs.
+            None if Instant::now() > deadline => {
+                child.kill().unwrap();
+                return true;
+            }
+            None => thread::sleep(Duration::from_millis(5)),
+        }
+    };
+    let mut stdout = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    // Without the standard library nothing of the program is run.
+    assert!(
+        !stdout.contains("FATAL ERROR"),
+        "Standard library is not loaded: {stdout}"
+    );
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    // Reported errors exit with status 1; aborts have no status.
+    matches!(status.code(), Some(0 | 1)) && !stderr.contains("panicked")
+}
+
+fn temp_file(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("proba-{name}-{}.proba", std::process::id()))
+}
+
+#[test]
+fn malformed_programs_do_not_panic() {
+    let file_path = temp_file("malformed");
+    let failed = PROGRAMS
+        .iter()
+        .filter(|program| !runs_without_panic(program, &file_path))
+        .collect::<Vec<_>>();
+    let _ = fs::remove_file(&file_path);
+    assert!(failed.is_empty(), "Interpreter panicked on: {failed:?}");
+}
+
+#[test]
+fn random_token_soups_do_not_panic() {
+    let file_path = temp_file("soup");
+    let mut random = Random(2024);
+    let mut failed = Vec::new();
+    for _ in 0..200 {
+        let length = 1 + random.next(25);
+        let program = (0..length)
+            .map(|_| TOKENS[random.next(TOKENS.len())])
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !runs_without_panic(&program, &file_path) {
+            failed.push(program);
+        }
+    }
+    let _ = fs::remove_file(&file_path);
+    assert!(failed.is_empty(), "Interpreter panicked on: {failed:?}");
+}